@@ -0,0 +1,140 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+// Length counter shared by all channels, it disables the channel when it reaches 0
+#[derive(Clone, Copy)]
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub counter: u16,
+    max: u16
+}
+
+impl LengthCounter {
+
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            max
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u16(self.counter);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = state.read_bool()?;
+        self.counter = state.read_u16()?;
+        if self.counter > self.max {
+            return Err(SaveStateError::InvalidDataError);
+        }
+        Ok(())
+    }
+
+    pub fn load(&mut self, length: u16) {
+        self.counter = self.max - length;
+    }
+
+    // Returns true when the channel has to be disabled
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // Handles the length enable and trigger bits of a NRx4 write. When the next frame
+    // sequencer step doesn't clock the length counter, enabling it clocks it once more.
+    // Returns true when the channel has to be disabled
+    pub fn write_control(&mut self, enable: bool, trigger: bool, next_step_clocks_length: bool) -> bool {
+
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        let mut disable = false;
+        if !next_step_clocks_length && !was_enabled && enable && self.counter > 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enable && !next_step_clocks_length {
+                self.counter -= 1;
+            }
+        }
+
+        disable
+    }
+
+}
+
+// Volume envelope used by the square and noise channels
+pub struct Envelope {
+    register: u8,
+    pub volume: u8,
+    timer: u8
+}
+
+impl Envelope {
+
+    pub fn new() -> Envelope {
+        Envelope {
+            register: 0x00,
+            volume: 0,
+            timer: 0
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.register, self.volume, self.timer]);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.register = state.read_u8()?;
+        self.volume = state.read_u8()? & 0x0F;
+        self.timer = state.read_u8()?;
+        Ok(())
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.register = data;
+    }
+
+    // The DAC is off when both the initial volume and the direction bit are 0
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.register & 0x07;
+    }
+
+    pub fn clock(&mut self) {
+
+        let period = self.register & 0x07;
+        if period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = period;
+            if self.register & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if self.register & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+}
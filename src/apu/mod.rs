@@ -0,0 +1,273 @@
+mod envelope;
+mod square;
+mod wave;
+mod noise;
+mod resampler;
+
+pub use self::resampler::Resampler;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use self::{square::SquareChannel, wave::WaveChannel, noise::NoiseChannel};
+
+
+// One stereo sample is produced every M-cycle
+pub const AUDIO_SAMPLE_RATE: u32 = 1_048_576;
+
+// Keep at most one second of audio if nobody is draining the buffer
+const MAX_BUFFERED_SAMPLES: usize = AUDIO_SAMPLE_RATE as usize * 2;
+
+// Charge factor of the output high pass filter capacitor for one M-cycle
+const HIGH_PASS_CHARGE: f32 = 0.999832;
+
+pub struct Apu {
+    enabled: bool,
+    nr50: u8,
+    nr51: u8,
+
+    ch1: SquareChannel,
+    ch2: SquareChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+
+    // Next frame sequencer step, clocked by the falling edge of DIV bit 12
+    // (bit 13 in double speed mode, so it keeps its 512 Hz rate)
+    frame_seq_step: u8,
+    last_div_bit: bool,
+
+    capacitors: [f32; 2],
+    samples: Vec<f32>,
+    // Separate copy of the output for the recorder. It is drained every frame,
+    // so it doesn't depend on the frontend taking the samples and has no cap
+    recorded_samples: Option<Vec<f32>>
+}
+
+impl Apu {
+
+    pub fn new() -> Apu {
+        Apu {
+            enabled: true,
+            nr50: 0x77,
+            nr51: 0xF3,
+            ch1: SquareChannel::new(true),
+            ch2: SquareChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+            frame_seq_step: 0,
+            last_div_bit: false,
+            capacitors: [0.0; 2],
+            samples: Vec::new(),
+            recorded_samples: None
+        }
+    }
+
+    // Samples that haven't been taken yet aren't part of the state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.nr50);
+        state.write_u8(self.nr51);
+        self.ch1.save_state(state);
+        self.ch2.save_state(state);
+        self.ch3.save_state(state);
+        self.ch4.save_state(state);
+        state.write_u8(self.frame_seq_step);
+        state.write_bool(self.last_div_bit);
+        state.write_f32(self.capacitors[0]);
+        state.write_f32(self.capacitors[1]);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = state.read_bool()?;
+        self.nr50 = state.read_u8()?;
+        self.nr51 = state.read_u8()?;
+        self.ch1.load_state(state)?;
+        self.ch2.load_state(state)?;
+        self.ch3.load_state(state)?;
+        self.ch4.load_state(state)?;
+        self.frame_seq_step = state.read_u8()? & 0x07;
+        self.last_div_bit = state.read_bool()?;
+        self.capacitors[0] = state.read_f32()?;
+        self.capacitors[1] = state.read_f32()?;
+        Ok(())
+    }
+
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF10 ..= 0xFF14 => self.ch1.read((addr - 0xFF10) as u8),
+            0xFF15 ..= 0xFF19 => self.ch2.read((addr - 0xFF15) as u8),
+            0xFF1A ..= 0xFF1E => self.ch3.read((addr - 0xFF1A) as u8),
+            0xFF1F ..= 0xFF23 => self.ch4.read((addr - 0xFF1F) as u8),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                0x70 | ((self.enabled as u8) << 7)
+                    | ((self.ch4.enabled as u8) << 3)
+                    | ((self.ch3.enabled as u8) << 2)
+                    | ((self.ch2.enabled as u8) << 1)
+                    | self.ch1.enabled as u8
+            },
+            0xFF30 ..= 0xFF3F => self.ch3.read_wave_ram(addr - 0xFF30),
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+
+        // While powered off only NR52, wave RAM and (on DMG) the length timers are writable
+        if !self.enabled {
+            match addr {
+                0xFF11 => self.ch1.length.load((data & 0x3F) as u16),
+                0xFF16 => self.ch2.length.load((data & 0x3F) as u16),
+                0xFF1B => self.ch3.length.load(data as u16),
+                0xFF20 => self.ch4.length.load((data & 0x3F) as u16),
+                _ => {}
+            }
+            if !matches!(addr, 0xFF26 | 0xFF30 ..= 0xFF3F) {
+                return;
+            }
+        }
+
+        let next_step_clocks_length = self.frame_seq_step & 0x01 == 0;
+
+        match addr {
+            0xFF10 ..= 0xFF14 => self.ch1.write((addr - 0xFF10) as u8, data, next_step_clocks_length),
+            0xFF15 ..= 0xFF19 => self.ch2.write((addr - 0xFF15) as u8, data, next_step_clocks_length),
+            0xFF1A ..= 0xFF1E => self.ch3.write((addr - 0xFF1A) as u8, data, next_step_clocks_length),
+            0xFF1F ..= 0xFF23 => self.ch4.write((addr - 0xFF1F) as u8, data, next_step_clocks_length),
+            0xFF24 => self.nr50 = data,
+            0xFF25 => self.nr51 = data,
+            0xFF26 => {
+                let enabled = data & 0x80 != 0;
+                if self.enabled && !enabled {
+                    self.power_off();
+                } else if !self.enabled && enabled {
+                    self.frame_seq_step = 0;
+                }
+                self.enabled = enabled;
+            },
+            0xFF30 ..= 0xFF3F => self.ch3.write_wave_ram(addr - 0xFF30, data),
+            _ => {}
+        }
+    }
+
+    fn power_off(&mut self) {
+        self.nr50 = 0x00;
+        self.nr51 = 0x00;
+        self.ch1.power_off();
+        self.ch2.power_off();
+        self.ch3.power_off();
+        self.ch4.power_off();
+    }
+
+    // `div` is the timer's internal 16 bit counter, shifted right once in double speed mode
+    pub fn tick(&mut self, m_cycles: u64, div: u16) {
+
+        for _ in 0..m_cycles {
+            if self.enabled {
+                self.ch1.step(4);
+                self.ch2.step(4);
+                self.ch3.step(4);
+                self.ch4.step(4);
+            }
+            self.push_sample();
+        }
+
+        let div_bit = div & (1 << 12) != 0;
+        if self.last_div_bit && !div_bit && self.enabled {
+            self.step_frame_sequencer();
+        }
+        self.last_div_bit = div_bit;
+    }
+
+    // While the system clock is stopped the APU doesn't run, but the output
+    // keeps going so the frontend isn't starved of audio
+    pub fn output_silence(&mut self, m_cycles: u64) {
+        for _ in 0..m_cycles {
+            self.output(0.0, 0.0);
+        }
+    }
+
+    // Step 0, 2, 4, 6: length. Step 2, 6: sweep. Step 7: envelope
+    fn step_frame_sequencer(&mut self) {
+
+        if self.frame_seq_step & 0x01 == 0 {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+
+        if self.frame_seq_step == 2 || self.frame_seq_step == 6 {
+            self.ch1.clock_sweep();
+        }
+
+        if self.frame_seq_step == 7 {
+            self.ch1.clock_envelope();
+            self.ch2.clock_envelope();
+            self.ch4.clock_envelope();
+        }
+
+        self.frame_seq_step = (self.frame_seq_step + 1) & 0x07;
+    }
+
+    fn push_sample(&mut self) {
+
+        // Each DAC maps the 0-15 channel output to -1.0 - 1.0, disabled DACs output 0
+        let dac = |enabled: bool, output: u8| if enabled { output as f32 / 7.5 - 1.0 } else { 0.0 };
+        let channels = [
+            dac(self.ch1.dac_enabled(), self.ch1.output()),
+            dac(self.ch2.dac_enabled(), self.ch2.output()),
+            dac(self.ch3.dac_enabled(), self.ch3.output()),
+            dac(self.ch4.dac_enabled(), self.ch4.output())
+        ];
+
+        // NR51 bits 0-3 route the channels to the right output and bits 4-7 to the left one
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (idx, sample) in channels.iter().enumerate() {
+            if self.nr51 & (0x10 << idx) != 0 {
+                left += sample;
+            }
+            if self.nr51 & (0x01 << idx) != 0 {
+                right += sample;
+            }
+        }
+
+        let left_volume = (((self.nr50 >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right_volume = ((self.nr50 & 0x07) + 1) as f32 / 8.0;
+        let left = self.high_pass(0, left / 4.0 * left_volume);
+        let right = self.high_pass(1, right / 4.0 * right_volume);
+        self.output(left, right);
+    }
+
+    fn output(&mut self, left: f32, right: f32) {
+        if self.samples.len() < MAX_BUFFERED_SAMPLES {
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+        if let Some(recorded) = self.recorded_samples.as_mut() {
+            recorded.push(left);
+            recorded.push(right);
+        }
+    }
+
+    // Removes the DC offset like the capacitor on the real hardware output
+    fn high_pass(&mut self, side: usize, input: f32) -> f32 {
+        let output = input - self.capacitors[side];
+        self.capacitors[side] = input - output * HIGH_PASS_CHARGE;
+        output
+    }
+
+    // Interleaved stereo samples at AUDIO_SAMPLE_RATE produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recorded_samples = if recording { Some(Vec::new()) } else { None };
+    }
+
+    // Samples produced since the last call while recording
+    pub fn take_recorded_samples(&mut self) -> Vec<f32> {
+        self.recorded_samples.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+}
@@ -0,0 +1,139 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::envelope::{Envelope, LengthCounter};
+
+
+pub struct NoiseChannel {
+    pub enabled: bool,
+
+    register: u8,
+    timer: u32,
+    lfsr: u16,
+
+    pub length: LengthCounter,
+    envelope: Envelope
+}
+
+impl NoiseChannel {
+
+    pub fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            register: 0x00,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new()
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_u8(self.register);
+        state.write_u32(self.timer);
+        state.write_u16(self.lfsr);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = state.read_bool()?;
+        self.register = state.read_u8()?;
+        self.timer = state.read_u32()?;
+        self.lfsr = state.read_u16()? & 0x7FFF;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        Ok(())
+    }
+
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            2 => self.envelope.read(),
+            3 => self.register,
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => 0xFF
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, data: u8, next_step_clocks_length: bool) {
+        match reg {
+            1 => self.length.load((data & 0x3F) as u16),
+            2 => {
+                self.envelope.write(data);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.register = data,
+            4 => {
+                let trigger = data & 0x80 != 0;
+                if self.length.write_control(data & 0x40 != 0, trigger, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.envelope.dac_enabled();
+                    self.timer = self.period();
+                    self.lfsr = 0x7FFF;
+                    self.envelope.trigger();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // NR43: clock shift (bits 7-4), LFSR width (bit 3) and divisor code (bits 2-0)
+    fn period(&self) -> u32 {
+        let divisor = match self.register & 0x07 {
+            0 => 8,
+            code => code as u32 * 16
+        };
+        divisor << (self.register >> 4)
+    }
+
+    pub fn power_off(&mut self) {
+        let counter = self.length.counter;
+        *self = NoiseChannel::new();
+        self.length.counter = counter;
+    }
+
+    pub fn step(&mut self, t_cycles: u32) {
+        let mut t_cycles = t_cycles;
+        while t_cycles > 0 {
+            let elapsed = t_cycles.min(self.timer.max(1));
+            self.timer = self.timer.saturating_sub(elapsed);
+            t_cycles -= elapsed;
+            if self.timer == 0 {
+                self.timer = self.period();
+
+                let xor = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+                self.lfsr = (self.lfsr >> 1) | (xor << 14);
+                // 7 bit mode also feeds the result back into bit 6
+                if self.register & 0x08 != 0 {
+                    self.lfsr = (self.lfsr & !0x40) | (xor << 6);
+                }
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        // The output is the inverted bit 0 of the LFSR
+        (!self.lfsr & 0x01) as u8 * self.envelope.volume
+    }
+
+}
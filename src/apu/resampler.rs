@@ -0,0 +1,128 @@
+use std::f64::consts::PI;
+
+
+// Taps of the band limited impulse and the number of sub-sample positions it is
+// precomputed for
+const KERNEL_WIDTH: usize = 32;
+const KERNEL_PHASES: usize = 256;
+
+// Cutoff of the impulse, as a fraction of the output sample rate
+const CUTOFF: f64 = 0.4;
+
+// Converts the interleaved stereo APU output to a lower sample rate. The APU output
+// only changes every now and then, so instead of filtering every input sample each
+// change is drawn as a band limited step: its delta is spread over the output
+// samples around it with a windowed sinc impulse, and the output is the running
+// sum of those impulses. Square wave harmonics above the output Nyquist frequency
+// are removed instead of aliasing back into the audible band
+pub struct Resampler {
+    base_step: f64,
+    step: f64,
+    kernel: Box<[f64]>,
+
+    // Position of the next input sample, in output samples from the start of `deltas`
+    time: f64,
+    last: [f32; 2],
+    deltas: Vec<[f64; 2]>,
+    sum: [f64; 2]
+}
+
+impl Resampler {
+
+    pub fn new(input_rate: u32, output_rate: u32) -> Resampler {
+        let step = input_rate as f64 / output_rate as f64;
+        Resampler {
+            base_step: step,
+            step,
+            kernel: build_kernel(),
+            time: 0.0,
+            last: [0.0; 2],
+            deltas: Vec::new(),
+            sum: [0.0; 2]
+        }
+    }
+
+    // Scales the output rate by `ratio`, e.g. 1.01 produces 1% more samples. Used
+    // by frontends to keep their audio queue from running dry or growing forever
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.step = self.base_step / ratio;
+    }
+
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+
+        let time_step = 1.0 / self.step;
+
+        for frame in input.chunks_exact(2) {
+
+            if frame[0] != self.last[0] || frame[1] != self.last[1] {
+                let delta = [(frame[0] - self.last[0]) as f64, (frame[1] - self.last[1]) as f64];
+                self.last = [frame[0], frame[1]];
+
+                let pos = self.time as usize;
+                let phase = ((self.time - pos as f64) * KERNEL_PHASES as f64) as usize;
+                let taps = &self.kernel[phase * KERNEL_WIDTH .. (phase + 1) * KERNEL_WIDTH];
+
+                if self.deltas.len() < pos + KERNEL_WIDTH {
+                    self.deltas.resize(pos + KERNEL_WIDTH, [0.0; 2]);
+                }
+                for (out, tap) in self.deltas[pos .. pos + KERNEL_WIDTH].iter_mut().zip(taps) {
+                    out[0] += delta[0] * tap;
+                    out[1] += delta[1] * tap;
+                }
+            }
+
+            self.time += time_step;
+        }
+
+        // Samples before the current position won't receive any more impulses
+        let ready = self.time as usize;
+        if self.deltas.len() < ready {
+            self.deltas.resize(ready, [0.0; 2]);
+        }
+        for delta in self.deltas.drain(.. ready) {
+            self.sum[0] += delta[0];
+            self.sum[1] += delta[1];
+            output.push(self.sum[0] as f32);
+            output.push(self.sum[1] as f32);
+        }
+        self.time -= ready as f64;
+    }
+
+}
+
+// Each tap is the part of a band limited step that falls within one output
+// sample, one row of taps per phase. The step is the running sum of a Blackman
+// windowed sinc sampled at every phase, so every row adds up to exactly 1
+fn build_kernel() -> Box<[f64]> {
+
+    let points = KERNEL_WIDTH * KERNEL_PHASES;
+    let half_width = KERNEL_WIDTH as f64 / 2.0;
+
+    let mut step = Vec::with_capacity(points);
+    let mut sum = 0.0;
+    for idx in 0..points {
+        let x = (idx as f64 + 0.5) / KERNEL_PHASES as f64 - half_width;
+        let sinc = (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x);
+        let window = 0.42 + 0.5 * (PI * x / half_width).cos() + 0.08 * (2.0 * PI * x / half_width).cos();
+        sum += sinc * window;
+        step.push(sum);
+    }
+    step.iter_mut().for_each(|value| *value /= sum);
+
+    // Level of the step `pos` phases after its start
+    let step_at = |pos: isize| match pos {
+        ..= 0 => 0.0,
+        pos if pos as usize >= points => 1.0,
+        pos => step[pos as usize - 1]
+    };
+
+    let mut kernel = vec![0.0; KERNEL_PHASES * KERNEL_WIDTH];
+    for (phase, taps) in kernel.chunks_exact_mut(KERNEL_WIDTH).enumerate() {
+        for (idx, tap) in taps.iter_mut().enumerate() {
+            let end = ((idx + 1) * KERNEL_PHASES - phase) as isize;
+            *tap = step_at(end) - step_at(end - KERNEL_PHASES as isize);
+        }
+    }
+
+    kernel.into_boxed_slice()
+}
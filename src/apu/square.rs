@@ -0,0 +1,263 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::envelope::{Envelope, LengthCounter};
+
+
+const DUTY_PATTERNS: [u8; 4] = [
+    0b0000_0001,  // 12.5%
+    0b1000_0001,  // 25%
+    0b1000_0111,  // 50%
+    0b0111_1110   // 75%
+];
+
+// Frequency sweep, only present on channel 1
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    shadow_frequency: u16,
+    timer: u8,
+    negate_used: bool
+}
+
+impl Sweep {
+
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn negate(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 is treated as 8
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    fn next_frequency(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.shift();
+        if self.negate() {
+            self.negate_used = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+
+}
+
+pub struct SquareChannel {
+    pub enabled: bool,
+
+    duty: u8,
+    duty_pos: u8,
+    frequency: u16,
+    timer: u32,
+
+    pub length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>
+}
+
+impl SquareChannel {
+
+    pub fn new(has_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            duty: 0,
+            duty_pos: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: if has_sweep {
+                Some(Sweep { register: 0x00, enabled: false, shadow_frequency: 0, timer: 0, negate_used: false })
+            } else {
+                None
+            }
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+
+        state.write_bool(self.enabled);
+        state.write_u8(self.duty);
+        state.write_u8(self.duty_pos);
+        state.write_u16(self.frequency);
+        state.write_u32(self.timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+
+        if let Some(sweep) = self.sweep.as_ref() {
+            state.write_u8(sweep.register);
+            state.write_bool(sweep.enabled);
+            state.write_u16(sweep.shadow_frequency);
+            state.write_u8(sweep.timer);
+            state.write_bool(sweep.negate_used);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+
+        self.enabled = state.read_bool()?;
+        self.duty = state.read_u8()? & 0x03;
+        self.duty_pos = state.read_u8()? & 0x07;
+        self.frequency = state.read_u16()? & 0x7FF;
+        self.timer = state.read_u32()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.register = state.read_u8()? & 0x7F;
+            sweep.enabled = state.read_bool()?;
+            sweep.shadow_frequency = state.read_u16()? & 0x7FF;
+            sweep.timer = state.read_u8()?;
+            sweep.negate_used = state.read_bool()?;
+        }
+
+        Ok(())
+    }
+
+    // Registers are indexed from 0 (NRx0) to 4 (NRx4)
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0 => match self.sweep.as_ref() {
+                Some(sweep) => 0x80 | sweep.register,
+                None => 0xFF
+            },
+            1 => 0x3F | (self.duty << 6),
+            2 => self.envelope.read(),
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => 0xFF
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, data: u8, next_step_clocks_length: bool) {
+        match reg {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.register = data & 0x7F;
+                    // Leaving negate mode after a calculation used it disables the channel
+                    if !sweep.negate() && sweep.negate_used {
+                        self.enabled = false;
+                    }
+                }
+            },
+            1 => {
+                self.duty = data >> 6;
+                self.length.load((data & 0x3F) as u16);
+            },
+            2 => {
+                self.envelope.write(data);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x0FF) | ((data as u16 & 0x07) << 8);
+                let trigger = data & 0x80 != 0;
+                if self.length.write_control(data & 0x40 != 0, trigger, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = (2048 - self.frequency as u32) * 4;
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow_frequency = self.frequency;
+            sweep.negate_used = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+            if sweep.shift() != 0 && sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    // On DMG the length counter is the only thing kept when the APU is powered off
+    pub fn power_off(&mut self) {
+        let counter = self.length.counter;
+        *self = SquareChannel::new(self.sweep.is_some());
+        self.length.counter = counter;
+    }
+
+    pub fn step(&mut self, t_cycles: u32) {
+        let mut t_cycles = t_cycles;
+        while t_cycles > 0 {
+            let elapsed = t_cycles.min(self.timer.max(1));
+            self.timer = self.timer.saturating_sub(elapsed);
+            t_cycles -= elapsed;
+            if self.timer == 0 {
+                self.timer = (2048 - self.frequency as u32) * 4;
+                self.duty_pos = (self.duty_pos + 1) & 0x07;
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+
+        let frequency = sweep.next_frequency();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if sweep.shift() != 0 {
+            sweep.shadow_frequency = frequency;
+            self.frequency = frequency;
+            // The new frequency is checked again for overflow but not written back
+            if sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_pos)) & 0x01;
+        high * self.envelope.volume
+    }
+
+}
@@ -0,0 +1,149 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::envelope::LengthCounter;
+
+
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    sample_buffer: u8,
+
+    pub length: LengthCounter,
+    wave_ram: [u8; 16]
+}
+
+impl WaveChannel {
+
+    pub fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            length: LengthCounter::new(256),
+            wave_ram: [0; 16]
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enabled);
+        state.write_bool(self.dac_enabled);
+        state.write_u8(self.volume_code);
+        state.write_u16(self.frequency);
+        state.write_u32(self.timer);
+        state.write_u8(self.position);
+        state.write_u8(self.sample_buffer);
+        self.length.save_state(state);
+        state.write_bytes(&self.wave_ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = state.read_bool()?;
+        self.dac_enabled = state.read_bool()?;
+        self.volume_code = state.read_u8()? & 0x03;
+        self.frequency = state.read_u16()? & 0x7FF;
+        self.timer = state.read_u32()?;
+        self.position = state.read_u8()? & 0x1F;
+        self.sample_buffer = state.read_u8()? & 0x0F;
+        self.length.load_state(state)?;
+        state.read_into(&mut self.wave_ram)?;
+        Ok(())
+    }
+
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0 => 0x7F | ((self.dac_enabled as u8) << 7),
+            2 => 0x9F | (self.volume_code << 5),
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => 0xFF
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, data: u8, next_step_clocks_length: bool) {
+        match reg {
+            0 => {
+                self.dac_enabled = data & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(data as u16),
+            2 => self.volume_code = (data >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x0FF) | ((data as u16 & 0x07) << 8);
+                let trigger = data & 0x80 != 0;
+                if self.length.write_control(data & 0x40 != 0, trigger, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.timer = (2048 - self.frequency as u32) * 2;
+                    self.position = 0;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    pub fn read_wave_ram(&self, addr: u16) -> u8 {
+        self.wave_ram[addr as usize]
+    }
+
+    pub fn write_wave_ram(&mut self, addr: u16, data: u8) {
+        self.wave_ram[addr as usize] = data;
+    }
+
+    // Wave RAM and the length counter survive powering the APU off
+    pub fn power_off(&mut self) {
+        let counter = self.length.counter;
+        let wave_ram = self.wave_ram;
+        *self = WaveChannel::new();
+        self.length.counter = counter;
+        self.wave_ram = wave_ram;
+    }
+
+    pub fn step(&mut self, t_cycles: u32) {
+        let mut t_cycles = t_cycles;
+        while t_cycles > 0 {
+            let elapsed = t_cycles.min(self.timer.max(1));
+            self.timer = self.timer.saturating_sub(elapsed);
+            t_cycles -= elapsed;
+            if self.timer == 0 {
+                self.timer = (2048 - self.frequency as u32) * 2;
+                self.position = (self.position + 1) & 0x1F;
+                let byte = self.wave_ram[self.position as usize / 2];
+                // Samples are stored upper nibble first
+                self.sample_buffer = if self.position & 0x01 == 0 { byte >> 4 } else { byte & 0x0F };
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        match self.volume_code {
+            0 => 0,
+            code => self.sample_buffer >> (code - 1)
+        }
+    }
+
+}
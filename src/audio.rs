@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use beni_boy_color::{Resampler, AUDIO_SAMPLE_RATE, FRAME_RATE};
+use sdl2::{audio::{AudioQueue, AudioSpecDesired}, AudioSubsystem};
+
+
+const DEVICE_SAMPLE_RATE: i32 = 48000;
+const DEVICE_BUFFER_SAMPLES: u16 = 512;
+
+// Amount of audio we try to keep queued, in frames. More latency, less risk of crackling
+const TARGET_QUEUED_FRAMES: f64 = 3.0;
+
+// Maximum deviation from the nominal resampling ratio used to correct the queue fill
+const MAX_RATE_ADJUST: f64 = 0.005;
+
+pub struct AudioOutput {
+    queue: AudioQueue<f32>,
+    resampler: Resampler,
+    buffer: Vec<f32>,
+    target_queued: f64
+}
+
+impl AudioOutput {
+
+    pub fn new(audio_subsystem: &AudioSubsystem) -> Result<AudioOutput, String> {
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(DEVICE_SAMPLE_RATE),
+            channels: Some(2),
+            samples: Some(DEVICE_BUFFER_SAMPLES)
+        };
+        let queue = audio_subsystem.open_queue::<f32, _>(None, &desired_spec)?;
+        queue.resume();
+
+        let device_rate = queue.spec().freq as u32;
+
+        Ok(AudioOutput {
+            queue,
+            resampler: Resampler::new(AUDIO_SAMPLE_RATE, device_rate),
+            buffer: Vec::new(),
+            target_queued: device_rate as f64 / FRAME_RATE * TARGET_QUEUED_FRAMES
+        })
+    }
+
+    // Stereo samples currently waiting in the device queue
+    fn queued_samples(&self) -> f64 {
+        (self.queue.size() as usize / (2 * std::mem::size_of::<f32>())) as f64
+    }
+
+    pub fn queue_samples(&mut self, samples: &[f32]) {
+
+        // Dynamic rate control: produce slightly less audio when the queue is fuller than
+        // the target and slightly more when it's running low. The pitch change is inaudible
+        let fill_error = (self.queued_samples() - self.target_queued) / self.target_queued;
+        let ratio = 1.0 - (fill_error * MAX_RATE_ADJUST).clamp(-MAX_RATE_ADJUST, MAX_RATE_ADJUST);
+        self.resampler.set_ratio(ratio);
+
+        self.buffer.clear();
+        self.resampler.process(samples, &mut self.buffer);
+        if let Err(err) = self.queue.queue_audio(&self.buffer) {
+            eprintln!("Failed to queue audio: {}", err);
+        }
+    }
+
+    // Paces emulation to the audio device: blocks while there is more audio queued
+    // than the target, so the device consumption rate drives the frame rate
+    pub fn wait(&self) {
+        while self.queued_samples() > self.target_queued {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+}
@@ -1,21 +1,237 @@
-use crate::cpu::Cpu;
+use std::path::Path;
+
+use crate::cartridge::{Cartridge, CartridgeHeader, InfraredPort};
+use crate::cpu::{Cpu, CpuStatus};
+use crate::error::EmulatorError;
+use crate::joypad::Button;
 use crate::mmu::Mmu;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::recorder::{Recorder, RecordingOptions};
+use crate::rewind::RewindBuffer;
+use crate::savestate::{StateReader, StateWriter};
+
 
+pub const FRAME_RATE: f64 = 59.7275;
+pub const M_CYCLES_PER_FRAME: u32 = 17556;
 
 pub struct BeniBoyColor {
     cpu: Cpu,
-    pub mmu: Mmu
+    mmu: Mmu,
+
+    recorder: Option<Recorder>,
+    recording_error: Option<std::io::Error>,
+
+    rewind: Option<RewindBuffer>
 }
 
 impl BeniBoyColor {
 
-    pub fn new(rom_path: &str) -> BeniBoyColor {
-        BeniBoyColor { cpu: Cpu::new(), mmu: Mmu::new(rom_path) }
+    // Battery backed RAM is loaded from and saved to the .sav file next to the ROM
+    pub fn new<P: AsRef<Path>>(rom_path: &P) -> Result<BeniBoyColor, EmulatorError> {
+        let cartridge = Cartridge::new(rom_path)?;
+        Ok(BeniBoyColor::with_cartridge(cartridge))
+    }
+
+    // Accepts both owned ROMs and borrowed slices, e.g. from include_bytes!()
+    pub fn from_rom_bytes<R: Into<Vec<u8>>>(rom: R) -> Result<BeniBoyColor, EmulatorError> {
+        let cartridge = Cartridge::from_rom(rom.into(), None)?;
+        Ok(BeniBoyColor::with_cartridge(cartridge))
+    }
+
+    fn with_cartridge(cartridge: Cartridge) -> BeniBoyColor {
+        BeniBoyColor {
+            cpu: Cpu::new(),
+            mmu: Mmu::new(cartridge),
+            recorder: None,
+            recording_error: None,
+            rewind: None
+        }
+    }
+
+    pub fn cpu_status(&self) -> CpuStatus {
+        self.cpu.status()
+    }
+
+    // Runs a single instruction and returns the M-cycles it took. The rest of
+    // the system is ticked by the CPU on every one of those cycles
+    pub fn tick(&mut self) -> u8 {
+        self.cpu.run_instruction(&mut self.mmu)
+    }
+
+    // Runs until the PPU finishes a frame. While the LCD is off or the system is
+    // stopped no frames are produced, so it stops after a frame's worth of cycles instead
+    pub fn run_frame(&mut self) {
+        let mut cycles = 0;
+        loop {
+            cycles += self.tick() as u32;
+            if self.mmu.ppu.take_frame_ready() {
+                break;
+            }
+            // The CPU runs twice as many cycles per frame in double speed mode
+            let frame_cycles = M_CYCLES_PER_FRAME << self.mmu.double_speed() as u8;
+            let producing_frames = self.mmu.ppu.lcd_enabled() && self.cpu.status() != CpuStatus::Stopped;
+            if cycles >= frame_cycles && !producing_frames {
+                break;
+            }
+        }
+        self.record_frame();
+
+        if self.rewind.is_some() {
+            let state = self.save_state();
+            if let Some(rewind) = self.rewind.as_mut() {
+                rewind.push(state);
+            }
+        }
+    }
+
+    // Keeps the state of the last `frames` frames so they can be stepped back
+    // through with rewind_frame(). 0 disables rewinding
+    pub fn set_rewind_frames(&mut self, frames: usize) {
+        self.rewind = match frames {
+            0 => None,
+            frames => Some(RewindBuffer::new(frames))
+        };
+    }
+
+    // Goes back to the previous frame, returns false once the buffer is exhausted.
+    // The states were made by this same machine, so they are restored without
+    // keeping a backup first
+    pub fn rewind_frame(&mut self) -> Result<bool, EmulatorError> {
+
+        // Taken out while loading, the state borrows from the buffer
+        let Some(mut rewind) = self.rewind.take() else {
+            return Ok(false);
+        };
+
+        let result = match rewind.pop() {
+            Some(state) => self.restore_state(state).map(|_| true),
+            None => Ok(false)
+        };
+
+        self.rewind = Some(rewind);
+        result
+    }
+
+    // Snapshot of the whole machine, it can only be loaded back with the same ROM
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new(self.mmu.rom_crc());
+        self.cpu.save_state(&mut state);
+        self.mmu.save_state(&mut state);
+        state.finish()
+    }
+
+    // The header is checked before touching the machine. If the rest of the
+    // state turns out to be corrupted the previous state is restored
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+
+        StateReader::new(data, self.mmu.rom_crc())?;
+        let backup = self.save_state();
+
+        if let Err(err) = self.restore_state(data) {
+            self.restore_state(&backup).expect("Invalid backup state!");
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    // Loads every component in turn, a corrupted state can leave the machine half loaded
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        let mut state = StateReader::new(data, self.mmu.rom_crc())?;
+        self.cpu.load_state(&mut state)?;
+        self.mmu.load_state(&mut state)?;
+        state.finish()?;
+        Ok(())
+    }
+
+    // Records the video and audio of every frame run from now on. Audio is
+    // recorded even before the game enables sound, as silence
+    pub fn start_recording(&mut self, options: &RecordingOptions) -> std::io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::new(options)?);
+        self.mmu.apu.set_recording(true);
+        Ok(())
     }
 
-    pub fn tick(&mut self) {
-        let instr_cycles = self.cpu.run_instruction(&mut self.mmu);
-        self.mmu.tick_components(instr_cycles as u64);
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        self.mmu.apu.set_recording(false);
+        match self.recorder.take() {
+            Some(mut recorder) => recorder.finish(),
+            None => Ok(())
+        }
+    }
+
+    // Recording stops on the first write error, which is kept until taken here
+    pub fn take_recording_error(&mut self) -> Option<std::io::Error> {
+        self.recording_error.take()
+    }
+
+    fn record_frame(&mut self) {
+
+        let Some(recorder) = self.recorder.as_mut() else {
+            return;
+        };
+
+        let samples = self.mmu.apu.take_recorded_samples();
+        let result = recorder.record_frame(self.mmu.ppu.screen.as_ref(), &samples);
+
+        if let Err(err) = result {
+            let _ = recorder.finish();
+            self.recorder = None;
+            self.mmu.apu.set_recording(false);
+            self.recording_error = Some(err);
+        }
+    }
+
+    // ARGB8888 pixels, SCREEN_WIDTH x SCREEN_HEIGHT
+    pub fn framebuffer(&self) -> &[u32; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        &self.mmu.ppu.screen
+    }
+
+    // Interleaved stereo samples at AUDIO_SAMPLE_RATE produced since the last call
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.mmu.apu.take_samples()
+    }
+
+    pub fn cartridge_header(&self) -> &CartridgeHeader {
+        self.mmu.cartridge_header()
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mmu.rumble()
+    }
+
+    pub fn save_ram(&mut self) -> std::io::Result<()> {
+        self.mmu.save_ram()
+    }
+
+    pub fn autosave(&mut self) -> std::io::Result<()> {
+        self.mmu.autosave()
+    }
+
+    pub fn set_infrared_port(&mut self, port: Box<dyn InfraredPort>) {
+        self.mmu.set_infrared_port(port);
+    }
+
+    // Replaces the whole joypad state with the given held buttons
+    pub fn set_buttons(&mut self, buttons: &[Button]) {
+        self.mmu.joypad.set_buttons(buttons, &mut self.mmu.interrupt_flag);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.mmu.joypad.press(button, &mut self.mmu.interrupt_flag);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.mmu.joypad.release(button);
+    }
+
+}
+
+impl Drop for BeniBoyColor {
+
+    fn drop(&mut self) {
+        let _ = self.stop_recording();
     }
 
 }
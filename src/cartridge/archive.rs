@@ -0,0 +1,80 @@
+use std::{io::{Cursor, Read}, path::{Path, PathBuf}};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use super::{CartridgeError, MAX_ROM_SIZE};
+
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+// Archives are detected from their contents rather than the file extension,
+// anything that isn't a zip or gzip archive is returned as is
+pub fn extract_rom(data: Vec<u8>) -> Result<Vec<u8>, CartridgeError> {
+    if data.starts_with(&ZIP_MAGIC) {
+        extract_zip(&data)
+    } else if data.starts_with(&GZIP_MAGIC) {
+        extract_gzip(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+fn extract_zip(data: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+
+    let mut archive = match ZipArchive::new(Cursor::new(data)) {
+        Ok(archive) => archive,
+        Err(_) => return Err(CartridgeError::ArchiveError)
+    };
+
+    let roms: Vec<usize> = (0..archive.len())
+        .filter(|idx| archive.name_for_index(*idx).is_some_and(is_rom_name))
+        .collect();
+
+    if roms.len() != 1 {
+        return Err(CartridgeError::ArchiveRomCountError(roms.len()));
+    }
+
+    let file = match archive.by_index(roms[0]) {
+        Ok(file) => file,
+        Err(_) => return Err(CartridgeError::ArchiveError)
+    };
+    read_rom(file)
+}
+
+fn extract_gzip(data: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    read_rom(GzDecoder::new(data))
+}
+
+// Stops decompressing past the largest ROM size, a tiny archive could
+// otherwise expand until it runs out of memory
+fn read_rom<R: Read>(reader: R) -> Result<Vec<u8>, CartridgeError> {
+
+    let mut rom = Vec::new();
+    if reader.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom).is_err() {
+        return Err(CartridgeError::ArchiveError);
+    }
+
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(CartridgeError::ArchiveRomTooLargeError);
+    }
+    Ok(rom)
+}
+
+// Path of a file stored next to the ROM, like the .sav file or the save state
+// slots. The archive extension is dropped too, so "game.gb.gz" and "game.zip"
+// both save to "game.sav"
+pub fn sibling_path(rom_path: &Path, extension: &str) -> PathBuf {
+    let is_archive = rom_path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz") || ext.eq_ignore_ascii_case("zip"));
+
+    let rom_path = if is_archive { rom_path.with_extension("") } else { rom_path.to_path_buf() };
+    rom_path.with_extension(extension)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
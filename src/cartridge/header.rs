@@ -0,0 +1,163 @@
+use std::fmt;
+
+use super::{CartridgeError, get_ext_ram_size, get_rom_size};
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CgbSupport {
+    DmgOnly,
+    CgbEnhanced,
+    CgbOnly
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Licensee {
+    Old(u8),
+    New(String)
+}
+
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub licensee: Licensee,
+    pub sgb_support: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+
+    computed_header_checksum: u8,
+    computed_global_checksum: u16
+}
+
+impl CartridgeHeader {
+
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+
+        if rom.len() < 0x150 {
+            return Err(CartridgeError::RomTooShortError(rom.len()));
+        }
+
+        let cgb_support = match rom[0x143] {
+            0xC0 => CgbSupport::CgbOnly,
+            flag if flag & 0x80 != 0 => CgbSupport::CgbEnhanced,
+            _ => CgbSupport::DmgOnly
+        };
+
+        // Newer carts use the end of the title for the manufacturer code and the
+        // CGB flag, there's no flag for it so we check if it looks like a code
+        let manufacturer_code = &rom[0x13F .. 0x143];
+        let has_manufacturer_code = cgb_support != CgbSupport::DmgOnly
+            && manufacturer_code.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+
+        let (title, manufacturer_code) = if has_manufacturer_code {
+            (&rom[0x134 .. 0x13F], Some(ascii_string(manufacturer_code)))
+        } else if cgb_support != CgbSupport::DmgOnly {
+            (&rom[0x134 .. 0x143], None)
+        } else {
+            (&rom[0x134 .. 0x144], None)
+        };
+
+        let licensee = match rom[0x14B] {
+            0x33 => Licensee::New(ascii_string(&rom[0x144 .. 0x146])),
+            code => Licensee::Old(code)
+        };
+
+        let rom_size = match get_rom_size(rom[0x148]) {
+            Some(size) => size,
+            None => return Err(CartridgeError::InvalidRomSizeError(rom[0x148]))
+        };
+
+        let ram_size = match get_ext_ram_size(rom[0x149]) {
+            Some(size) => size,
+            None => return Err(CartridgeError::InvalidRamSizeError(rom[0x149]))
+        };
+
+        let computed_header_checksum = rom[0x134 ..= 0x14C]
+            .iter()
+            .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+
+        // The global checksum adds every byte of the ROM except the checksum itself
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| *idx != 0x14E && *idx != 0x14F)
+            .fold(0u16, |checksum, (_, byte)| checksum.wrapping_add(*byte as u16));
+
+        Ok(CartridgeHeader {
+            title: ascii_string(title),
+            manufacturer_code,
+            cgb_support,
+            licensee,
+            sgb_support: rom[0x146] == 0x03,
+            cartridge_type: rom[0x147],
+            rom_size,
+            ram_size,
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum: (rom[0x14E] as u16) << 8 | rom[0x14F] as u16,
+            computed_header_checksum,
+            computed_global_checksum
+        })
+    }
+
+    pub fn computed_header_checksum(&self) -> u8 {
+        self.computed_header_checksum
+    }
+
+    pub fn computed_global_checksum(&self) -> u16 {
+        self.computed_global_checksum
+    }
+
+    // The boot ROM refuses to run a cart with a bad header checksum
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    // The global checksum isn't checked by the hardware, plenty of homebrew gets it wrong
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+}
+
+impl fmt::Display for CartridgeHeader {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Title:           {}", self.title)?;
+        if let Some(code) = self.manufacturer_code.as_ref() {
+            writeln!(f, "Manufacturer:    {}", code)?;
+        }
+        writeln!(f, "CGB support:     {:?}", self.cgb_support)?;
+        match &self.licensee {
+            Licensee::Old(code) => writeln!(f, "Licensee:        {:02X} (old)", code)?,
+            Licensee::New(code) => writeln!(f, "Licensee:        {} (new)", code)?
+        }
+        writeln!(f, "SGB support:     {}", self.sgb_support)?;
+        writeln!(f, "Cartridge type:  {:02X}", self.cartridge_type)?;
+        writeln!(f, "ROM size:        {} KiB", self.rom_size / 1024)?;
+        writeln!(f, "RAM size:        {} KiB", self.ram_size / 1024)?;
+        writeln!(f, "Version:         {}", self.version)?;
+        writeln!(f, "Header checksum: {:02X} ({})", self.header_checksum, checksum_status(self.header_checksum_valid()))?;
+        write!(f, "Global checksum: {:04X} ({})", self.global_checksum, checksum_status(self.global_checksum_valid()))
+    }
+
+}
+
+fn checksum_status(valid: bool) -> &'static str {
+    if valid { "ok" } else { "mismatch" }
+}
+
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|c| **c != 0x00)
+        .map(|c| if c.is_ascii_graphic() || *c == b' ' { *c as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
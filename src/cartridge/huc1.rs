@@ -0,0 +1,99 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::{Mbc, ROM_BANK_SIZE, RAM_BANK_SIZE, infrared::{InfraredPort, NoInfrared}};
+
+
+pub struct Huc1 {
+    rom_banks: usize,
+
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+
+    ir_port: Box<dyn InfraredPort>
+}
+
+impl Huc1 {
+
+    pub fn new(rom_size: usize) -> Huc1 {
+        Huc1 {
+            rom_banks: rom_size / ROM_BANK_SIZE,
+            ir_mode: false,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            ir_port: Box::new(NoInfrared)
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> usize {
+        (self.ram_bank as usize * RAM_BANK_SIZE + addr as usize) % ram.len()
+    }
+
+}
+
+impl Mbc for Huc1 {
+
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom[addr as usize],
+            _ => {
+                let bank = self.rom_bank as usize % self.rom_banks;
+                rom[bank * ROM_BANK_SIZE + (addr - 0x4000) as usize]
+            }
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            // 0x0E maps the IR port to 0xA000 - 0xBFFF, any other value maps the RAM
+            0x0000 ..= 0x1FFF => self.ir_mode = data & 0x0F == 0x0E,
+
+            // ROM bank number
+            0x2000 ..= 0x3FFF => self.rom_bank = data & 0x3F,
+
+            // RAM bank number
+            0x4000 ..= 0x5FFF => self.ram_bank = data & 0x03,
+
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if self.ir_mode {
+            0xC0 | self.ir_port.light_detected() as u8
+        } else if ram.is_empty() {
+            0xFF
+        } else {
+            ram[self.ram_offset(ram, addr)]
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if self.ir_mode {
+            self.ir_port.set_led(data & 0x01 != 0);
+            false
+        } else if !ram.is_empty() {
+            ram[self.ram_offset(ram, addr)] = data;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ir_mode);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.ram_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ir_mode = state.read_bool()?;
+        self.rom_bank = state.read_u8()? & 0x3F;
+        self.ram_bank = state.read_u8()? & 0x03;
+        Ok(())
+    }
+
+    fn set_infrared_port(&mut self, port: Box<dyn InfraredPort>) {
+        self.ir_port = port;
+    }
+
+}
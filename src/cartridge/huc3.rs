@@ -0,0 +1,230 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::{Mbc, ROM_BANK_SIZE, RAM_BANK_SIZE, infrared::{InfraredPort, NoInfrared}, rtc::unix_time};
+
+
+const MINUTES_PER_DAY: u64 = 1440;
+
+// HuC3 clock, it counts minutes of the day (12 bits) and days (16 bits).
+// Like the MBC3 RTC it keeps advancing from the host clock
+struct Huc3Clock {
+    minutes: u16,
+    days: u16,
+    seconds: u8,
+    last_update: u64
+}
+
+impl Huc3Clock {
+
+    fn update(&mut self) {
+        let now = unix_time();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        let total = self.seconds as u64 + elapsed;
+        self.seconds = (total % 60) as u8;
+
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % MINUTES_PER_DAY) as u16;
+        self.days = self.days.wrapping_add((total / MINUTES_PER_DAY) as u16);
+    }
+
+    // .sav footer in SameBoy's layout: the 64 bit timestamp of the start of the
+    // current minute, minutes and days as 16 bit values, then the alarm minutes,
+    // days (16 bits) and enable flag (8 bits). The alarm isn't emulated, so it's
+    // saved as zeros and ignored when loading
+    fn save(&self, out: &mut Vec<u8>) {
+        let minute_start = self.last_update.saturating_sub(self.seconds as u64);
+        out.extend_from_slice(&minute_start.to_le_bytes());
+        out.extend_from_slice(&self.minutes.to_le_bytes());
+        out.extend_from_slice(&self.days.to_le_bytes());
+        out.extend_from_slice(&[0; 5]);
+    }
+
+    fn load(&mut self, data: &[u8]) {
+
+        if data.len() != 17 {
+            return;
+        }
+
+        self.last_update = u64::from_le_bytes(data[0 .. 8].try_into().expect("Array size mismatch!"));
+        self.minutes = u16::from_le_bytes([data[8], data[9]]) % MINUTES_PER_DAY as u16;
+        self.days = u16::from_le_bytes([data[10], data[11]]);
+        self.seconds = 0;
+
+        // Catch up with the time that passed while the game wasn't running
+        self.update();
+    }
+
+}
+
+pub struct Huc3 {
+    rom_banks: usize,
+
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+
+    // RTC command interface, the clock is accessed a nibble at a time
+    access_index: u8,
+    response: u8,
+    clock: Huc3Clock,
+
+    ir_port: Box<dyn InfraredPort>
+}
+
+impl Huc3 {
+
+    pub fn new(rom_size: usize) -> Huc3 {
+        Huc3 {
+            rom_banks: rom_size / ROM_BANK_SIZE,
+            mode: 0x00,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            access_index: 0x00,
+            response: 0x00,
+            clock: Huc3Clock { minutes: 0, days: 0, seconds: 0, last_update: unix_time() },
+            ir_port: Box::new(NoInfrared)
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> usize {
+        (self.ram_bank as usize * RAM_BANK_SIZE + addr as usize) % ram.len()
+    }
+
+    // Commands are written as (command << 4) | argument, returns whether the clock was set
+    fn rtc_command(&mut self, data: u8) -> bool {
+
+        let arg = data & 0x0F;
+        self.clock.update();
+
+        match data >> 4 {
+
+            // Read the nibble at the access index and advance it
+            0x1 => {
+                self.response = match self.access_index {
+                    0x00 ..= 0x02 => (self.clock.minutes >> (self.access_index * 4)) as u8 & 0x0F,
+                    0x03 ..= 0x06 => (self.clock.days >> ((self.access_index - 3) * 4)) as u8 & 0x0F,
+                    _ => 0x00
+                };
+                self.access_index = self.access_index.wrapping_add(1);
+            },
+
+            // Write the nibble at the access index, command 3 also advances it
+            0x2 | 0x3 => {
+                match self.access_index {
+                    0x00 ..= 0x02 => {
+                        let shift = self.access_index * 4;
+                        self.clock.minutes = (self.clock.minutes & !(0x0F << shift)) | ((arg as u16) << shift);
+                    },
+                    0x03 ..= 0x06 => {
+                        let shift = (self.access_index - 3) * 4;
+                        self.clock.days = (self.clock.days & !(0x0F << shift)) | ((arg as u16) << shift);
+                    },
+                    _ => {}
+                }
+                if data >> 4 == 0x3 {
+                    self.access_index = self.access_index.wrapping_add(1);
+                }
+            },
+
+            // Set the low / high nibble of the access index
+            0x4 => self.access_index = (self.access_index & 0xF0) | arg,
+            0x5 => self.access_index = (self.access_index & 0x0F) | (arg << 4),
+
+            _ => {}
+        }
+
+        matches!(data >> 4, 0x2 | 0x3)
+    }
+
+}
+
+impl Mbc for Huc3 {
+
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom[addr as usize],
+            _ => {
+                let bank = self.rom_bank as usize % self.rom_banks;
+                rom[bank * ROM_BANK_SIZE + (addr - 0x4000) as usize]
+            }
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            // Selects what is mapped to 0xA000 - 0xBFFF:
+            // 0x0 RAM (read only), 0xA RAM, 0xB RTC command, 0xC RTC response,
+            // 0xD RTC semaphore, 0xE IR port
+            0x0000 ..= 0x1FFF => self.mode = data & 0x0F,
+
+            // ROM bank number
+            0x2000 ..= 0x3FFF => self.rom_bank = data & 0x7F,
+
+            // RAM bank number
+            0x4000 ..= 0x5FFF => self.ram_bank = data & 0x03,
+
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xA if !ram.is_empty() => ram[self.ram_offset(ram, addr)],
+            0xC => 0x80 | self.response,
+            // The RTC is always ready to take a new command
+            0xD => 0x01,
+            0xE => 0xC0 | self.ir_port.light_detected() as u8,
+            _ => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        match self.mode {
+            0xA if !ram.is_empty() => {
+                ram[self.ram_offset(ram, addr)] = data;
+                true
+            },
+            0xB => self.rtc_command(data),
+            0xE => {
+                self.ir_port.set_led(data & 0x01 != 0);
+                false
+            },
+            _ => false
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.mode, self.rom_bank, self.ram_bank, self.access_index, self.response]);
+        state.write_u16(self.clock.minutes);
+        state.write_u16(self.clock.days);
+        state.write_u8(self.clock.seconds);
+        state.write_u64(self.clock.last_update);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.mode = state.read_u8()? & 0x0F;
+        self.rom_bank = state.read_u8()? & 0x7F;
+        self.ram_bank = state.read_u8()? & 0x03;
+        self.access_index = state.read_u8()?;
+        self.response = state.read_u8()? & 0x0F;
+        self.clock.minutes = state.read_u16()? % MINUTES_PER_DAY as u16;
+        self.clock.days = state.read_u16()?;
+        self.clock.seconds = state.read_u8()? % 60;
+        self.clock.last_update = state.read_u64()?;
+        Ok(())
+    }
+
+    fn save_rtc(&self, out: &mut Vec<u8>) {
+        self.clock.save(out);
+    }
+
+    fn load_rtc(&mut self, data: &[u8]) {
+        self.clock.load(data);
+    }
+
+    fn set_infrared_port(&mut self, port: Box<dyn InfraredPort>) {
+        self.ir_port = port;
+    }
+
+}
@@ -0,0 +1,19 @@
+// Infrared port found on Hudson's HuC1 and HuC3 cartridges. Frontends can plug
+// in their own implementation to link two emulators or talk to real hardware
+pub trait InfraredPort {
+    fn set_led(&mut self, on: bool);
+    fn light_detected(&self) -> bool;
+}
+
+// Port with nothing in front of it, it never receives any light
+pub struct NoInfrared;
+
+impl InfraredPort for NoInfrared {
+
+    fn set_led(&mut self, _on: bool) {}
+
+    fn light_detected(&self) -> bool {
+        false
+    }
+
+}
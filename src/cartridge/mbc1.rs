@@ -0,0 +1,117 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::{Mbc, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+
+pub struct Mbc1 {
+    rom_banks: usize,
+    multicart: bool,
+
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    banking_mode: u8
+}
+
+impl Mbc1 {
+
+    pub fn new(rom_size: usize, multicart: bool) -> Mbc1 {
+        Mbc1 {
+            rom_banks: rom_size / ROM_BANK_SIZE,
+            multicart,
+            ram_enabled: false,
+            bank1: 0x01,
+            bank2: 0x00,
+            banking_mode: 0
+        }
+    }
+
+    // MBC1M multicarts don't connect bit 4 of the ROM bank register, so the
+    // upper register is shifted one bit less
+    fn upper_bank_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn lower_bank(&self) -> usize {
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+        bank1 as usize
+    }
+
+    fn rom_bank_0(&self) -> usize {
+        if self.banking_mode == 1 {
+            ((self.bank2 as usize) << self.upper_bank_shift()) % self.rom_banks
+        } else {
+            0
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        (((self.bank2 as usize) << self.upper_bank_shift()) | self.lower_bank()) % self.rom_banks
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> usize {
+        let bank = if self.banking_mode == 1 { self.bank2 as usize } else { 0 };
+        (bank * RAM_BANK_SIZE + addr as usize) % ram.len()
+    }
+
+}
+
+impl Mbc for Mbc1 {
+
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom[self.rom_bank_0() * ROM_BANK_SIZE + addr as usize],
+            _ => rom[self.rom_bank() * ROM_BANK_SIZE + (addr - 0x4000) as usize]
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            // RAM enable
+            0x0000 ..= 0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+
+            // ROM bank number, bank 0 can't be selected and maps to bank 1 instead.
+            // The check is done on the full 5 bits, so e.g. bank 0x20 maps to 0x21
+            0x2000 ..= 0x3FFF => {
+                self.bank1 = data & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            },
+
+            // RAM bank number / upper bits of the ROM bank number
+            0x4000 ..= 0x5FFF => self.bank2 = data & 0x03,
+
+            // Banking mode select
+            _ => self.banking_mode = data & 0x01
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled || ram.is_empty() {
+            return 0xFF;
+        }
+        ram[self.ram_offset(ram, addr)]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if !self.ram_enabled || ram.is_empty() {
+            return false;
+        }
+        ram[self.ram_offset(ram, addr)] = data;
+        true
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_bytes(&[self.bank1, self.bank2, self.banking_mode]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.bank1 = (state.read_u8()? & 0x1F).max(1);
+        self.bank2 = state.read_u8()? & 0x03;
+        self.banking_mode = state.read_u8()? & 0x01;
+        Ok(())
+    }
+
+}
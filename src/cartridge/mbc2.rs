@@ -0,0 +1,83 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::{Mbc, ROM_BANK_SIZE};
+
+
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+// MBC2 has 512 half-bytes of RAM built in, mirrored through the whole
+// 0xA000 - 0xBFFF range. Only the lower 4 bits of each byte are used
+pub struct Mbc2 {
+    rom_banks: usize,
+
+    ram_enabled: bool,
+    rom_bank: u8
+}
+
+impl Mbc2 {
+
+    pub fn new(rom_size: usize) -> Mbc2 {
+        Mbc2 {
+            rom_banks: rom_size / ROM_BANK_SIZE,
+            ram_enabled: false,
+            rom_bank: 0x01
+        }
+    }
+
+}
+
+impl Mbc for Mbc2 {
+
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom[addr as usize],
+            _ => {
+                let bank = self.rom_bank as usize % self.rom_banks;
+                rom[bank * ROM_BANK_SIZE + (addr - 0x4000) as usize]
+            }
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+
+        if addr > 0x3FFF {
+            return;
+        }
+
+        // Bit 8 of the address selects between RAM enable and ROM bank number
+        if addr & 0x0100 == 0 {
+            self.ram_enabled = data & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = data & 0x0F;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        0xF0 | ram[addr as usize % MBC2_RAM_SIZE]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        ram[addr as usize % MBC2_RAM_SIZE] = data & 0x0F;
+        true
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.rom_bank);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = (state.read_u8()? & 0x0F).max(1);
+        Ok(())
+    }
+
+}
@@ -0,0 +1,131 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::{Mbc, ROM_BANK_SIZE, RAM_BANK_SIZE, rtc::Rtc};
+
+
+pub struct Mbc3 {
+    rom_banks: usize,
+
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_select: u8,
+    latch_armed: bool,
+
+    rtc: Option<Rtc>
+}
+
+impl Mbc3 {
+
+    pub fn new(rom_size: usize, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            rom_banks: rom_size / ROM_BANK_SIZE,
+            ram_enabled: false,
+            rom_bank: 0x01,
+            ram_select: 0x00,
+            latch_armed: false,
+            rtc: if has_rtc { Some(Rtc::new()) } else { None }
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> usize {
+        (self.ram_select as usize * RAM_BANK_SIZE + addr as usize) % ram.len()
+    }
+
+}
+
+impl Mbc for Mbc3 {
+
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom[addr as usize],
+            _ => {
+                let bank = self.rom_bank as usize % self.rom_banks;
+                rom[bank * ROM_BANK_SIZE + (addr - 0x4000) as usize]
+            }
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            // RAM and RTC enable
+            0x0000 ..= 0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+
+            // ROM bank number, bank 0 maps to bank 1
+            0x2000 ..= 0x3FFF => {
+                self.rom_bank = data & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+
+            // RAM bank number (0x00 - 0x03) or RTC register select (0x08 - 0x0C)
+            0x4000 ..= 0x5FFF => self.ram_select = data,
+
+            // Writing 0x00 and then 0x01 latches the current time into the RTC registers
+            _ => {
+                if self.latch_armed && data == 0x01 {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.latch();
+                    }
+                }
+                self.latch_armed = data == 0x00;
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_select, self.rtc.as_ref()) {
+            (0x00 ..= 0x03, _) if !ram.is_empty() => ram[self.ram_offset(ram, addr)],
+            (0x08 ..= 0x0C, Some(rtc)) => rtc.read(self.ram_select),
+            _ => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        match (self.ram_select, self.rtc.as_mut()) {
+            (0x00 ..= 0x03, _) if !ram.is_empty() => ram[self.ram_offset(ram, addr)] = data,
+            (0x08 ..= 0x0C, Some(rtc)) => rtc.write(self.ram_select, data),
+            _ => return false
+        }
+        true
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.ram_select);
+        state.write_bool(self.latch_armed);
+        if let Some(rtc) = self.rtc.as_ref() {
+            rtc.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = (state.read_u8()? & 0x7F).max(1);
+        self.ram_select = state.read_u8()?;
+        self.latch_armed = state.read_bool()?;
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load_state(state)?;
+        }
+        Ok(())
+    }
+
+    fn save_rtc(&self, out: &mut Vec<u8>) {
+        if let Some(rtc) = self.rtc.as_ref() {
+            rtc.save(out);
+        }
+    }
+
+    fn load_rtc(&mut self, data: &[u8]) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load(data);
+        }
+    }
+
+}
@@ -0,0 +1,105 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::{Mbc, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+
+pub struct Mbc5 {
+    rom_banks: usize,
+    has_rumble: bool,
+
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    rumble: bool
+}
+
+impl Mbc5 {
+
+    pub fn new(rom_size: usize, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            rom_banks: rom_size / ROM_BANK_SIZE,
+            has_rumble,
+            ram_enabled: false,
+            rom_bank: 0x001,
+            ram_bank: 0x00,
+            rumble: false
+        }
+    }
+
+    fn ram_offset(&self, ram: &[u8], addr: u16) -> usize {
+        (self.ram_bank as usize * RAM_BANK_SIZE + addr as usize) % ram.len()
+    }
+
+}
+
+impl Mbc for Mbc5 {
+
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x3FFF => rom[addr as usize],
+            _ => {
+                let bank = self.rom_bank as usize % self.rom_banks;
+                rom[bank * ROM_BANK_SIZE + (addr - 0x4000) as usize]
+            }
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            // RAM enable
+            0x0000 ..= 0x1FFF => self.ram_enabled = data == 0x0A,
+
+            // Lower 8 bits of the ROM bank number. Unlike other MBCs, bank 0 can be mapped here
+            0x2000 ..= 0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | data as u16,
+
+            // 9th bit of the ROM bank number
+            0x3000 ..= 0x3FFF => self.rom_bank = (self.rom_bank & 0x0FF) | ((data as u16 & 0x01) << 8),
+
+            // RAM bank number. On rumble carts bit 3 drives the motor instead
+            0x4000 ..= 0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = data & 0x08 != 0;
+                    self.ram_bank = data & 0x07;
+                } else {
+                    self.ram_bank = data & 0x0F;
+                }
+            },
+
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled || ram.is_empty() {
+            return 0xFF;
+        }
+        ram[self.ram_offset(ram, addr)]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if !self.ram_enabled || ram.is_empty() {
+            return false;
+        }
+        ram[self.ram_offset(ram, addr)] = data;
+        true
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.ram_enabled);
+        state.write_u16(self.rom_bank);
+        state.write_u8(self.ram_bank);
+        state.write_bool(self.rumble);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.ram_enabled = state.read_bool()?;
+        self.rom_bank = state.read_u16()? & 0x1FF;
+        self.ram_bank = state.read_u8()? & 0x0F;
+        self.rumble = state.read_bool()?;
+        Ok(())
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+}
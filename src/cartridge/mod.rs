@@ -1,21 +1,110 @@
-use std::path::Path;
+mod no_mbc;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod huc1;
+mod huc3;
+mod rtc;
+mod infrared;
+mod save;
+mod header;
+mod archive;
 
+use std::{fmt, path::{Path, PathBuf}, time::Instant};
+
+use flate2::Crc;
+
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub use self::infrared::InfraredPort;
+pub use self::archive::sibling_path;
+pub use self::header::{CartridgeHeader, CgbSupport, Licensee};
+use self::{no_mbc::NoMbc, mbc1::Mbc1, mbc2::{Mbc2, MBC2_RAM_SIZE}, mbc3::Mbc3, mbc5::Mbc5, huc1::Huc1, huc3::Huc3};
+
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const MAX_ROM_SIZE: usize = 0x800000;
+
+// Memory bank controller. It receives the cartridge ROM and RAM so it can map
+// the CPU address ranges to them
+trait Mbc {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8;
+    fn write_rom(&mut self, addr: u16, data: u8);
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8;
+    // Returns whether the write reached the RAM or the clock, anything that goes into the save file
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool;
+
+    // Bank registers and any other state of the controller itself
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError>;
+
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    fn set_infrared_port(&mut self, _port: Box<dyn InfraredPort>) {}
+
+    fn save_rtc(&self, _out: &mut Vec<u8>) {}
+
+    fn load_rtc(&mut self, _data: &[u8]) {}
+}
 
 pub struct Cartridge {
+    header: CartridgeHeader,
     rom: Box<[u8]>,
-    rom_size: usize,
-    rom_bank: usize,
-
     external_ram: Box<[u8]>,
-    external_ram_bank: usize
+    mbc: Box<dyn Mbc>,
+    // CRC32 of the whole ROM, it identifies the game in save states
+    rom_crc: u32,
+
+    // Only set for battery backed cartridges
+    save_path: Option<PathBuf>,
+    last_ram_write: Option<Instant>
 }
 
 #[derive(Debug)]
 pub enum CartridgeError {
     RomReadError,
-    InvalidRomError
+    RomTooShortError(usize),
+    RomSizeMismatchError { header_size: usize, file_size: usize },
+    InvalidRomSizeError(u8),
+    InvalidRamSizeError(u8),
+    HeaderChecksumError { expected: u8, computed: u8 },
+    UnsupportedMbcError(u8),
+    SaveReadError,
+    ArchiveError,
+    ArchiveRomCountError(usize),
+    ArchiveRomTooLargeError
+}
+
+impl fmt::Display for CartridgeError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::RomReadError => write!(f, "couldn't read the ROM file"),
+            CartridgeError::RomTooShortError(size) => write!(f, "ROM is too short to contain a header ({} bytes)", size),
+            CartridgeError::RomSizeMismatchError { header_size, file_size } =>
+                write!(f, "header ROM size ({} bytes) doesn't match the file size ({} bytes)", header_size, file_size),
+            CartridgeError::InvalidRomSizeError(byte) => write!(f, "invalid ROM size in header ({:02X})", byte),
+            CartridgeError::InvalidRamSizeError(byte) => write!(f, "invalid RAM size in header ({:02X})", byte),
+            CartridgeError::HeaderChecksumError { expected, computed } =>
+                write!(f, "header checksum mismatch (expected {:02X}, computed {:02X})", expected, computed),
+            CartridgeError::UnsupportedMbcError(cart_type) => write!(f, "unsupported cartridge type ({:02X})", cart_type),
+            CartridgeError::SaveReadError => write!(f, "couldn't read the save file"),
+            CartridgeError::ArchiveError => write!(f, "couldn't extract the ROM from the archive"),
+            CartridgeError::ArchiveRomCountError(count) =>
+                write!(f, "archive must contain exactly one .gb/.gbc file, found {}", count),
+            CartridgeError::ArchiveRomTooLargeError =>
+                write!(f, "ROM in the archive is larger than {} bytes", MAX_ROM_SIZE)
+        }
+    }
+
 }
 
+impl std::error::Error for CartridgeError {}
+
 impl Cartridge {
 
     pub fn new<P: AsRef<Path>>(rom_path: &P)-> Result<Cartridge, CartridgeError> {
@@ -25,51 +114,137 @@ impl Cartridge {
             Ok(rom) => rom,
             Err(_) => return Err(CartridgeError::RomReadError)
         };
-        
-        // First we need to check if the ROM file is long enough to check some fields
-        // from its header
+
+        Cartridge::from_rom(rom, Some(archive::sibling_path(rom_path.as_ref(), "sav")))
+    }
+
+    // Battery backed RAM is only persisted when a save path is given.
+    // The ROM can also be a zip or gzip archive containing a single ROM
+    pub fn from_rom(rom: Vec<u8>, save_path: Option<PathBuf>) -> Result<Cartridge, CartridgeError> {
+
+        let rom = archive::extract_rom(rom)?;
+        let header = CartridgeHeader::parse(&rom)?;
+
         let rom_size = rom.len();
-        if rom_size < 0x150 {
-            return Err(CartridgeError::InvalidRomError)
+        if header.rom_size != rom_size {
+            return Err(CartridgeError::RomSizeMismatchError { header_size: header.rom_size, file_size: rom_size });
         }
 
-        match get_rom_size(rom[0x148]) {
-            Some(size) if size == rom_size => {},
-            _ => return Err(CartridgeError::InvalidRomError)
+        if !header.header_checksum_valid() {
+            return Err(CartridgeError::HeaderChecksumError {
+                expected: header.header_checksum,
+                computed: header.computed_header_checksum()
+            });
         }
 
-        let external_ram_size = match get_ext_ram_size(rom[0x149]) {
-            Some(size) => size,
-            None => return Err(CartridgeError::InvalidRomError)
+        let mut external_ram_size = header.ram_size;
+
+        let mbc: Box<dyn Mbc> = match header.cartridge_type {
+            0x00 | 0x08 | 0x09 => Box::new(NoMbc),
+            0x01 ..= 0x03 => Box::new(Mbc1::new(rom_size, is_mbc1_multicart(&rom))),
+            0x05 | 0x06 => {
+                // The RAM is inside the MBC2 itself, so the header reports no RAM
+                external_ram_size = MBC2_RAM_SIZE;
+                Box::new(Mbc2::new(rom_size))
+            },
+            0x0F | 0x10 => Box::new(Mbc3::new(rom_size, true)),
+            0x11 ..= 0x13 => Box::new(Mbc3::new(rom_size, false)),
+            0x19 ..= 0x1B => Box::new(Mbc5::new(rom_size, false)),
+            0x1C ..= 0x1E => Box::new(Mbc5::new(rom_size, true)),
+            0xFE => Box::new(Huc3::new(rom_size)),
+            0xFF => Box::new(Huc1::new(rom_size)),
+            cart_type => return Err(CartridgeError::UnsupportedMbcError(cart_type))
         };
 
-        Ok(Cartridge { 
+        let save_path = save_path.filter(|_| has_battery(header.cartridge_type));
+
+        let mut crc = Crc::new();
+        crc.update(&rom);
+
+        let mut cartridge = Cartridge { 
+            header,
             rom: rom.into_boxed_slice(),
-            rom_size: rom_size,
-            rom_bank: 0x4000,
             external_ram: vec![0; external_ram_size].into_boxed_slice(),
-            external_ram_bank: 0x0000
-        })
+            mbc,
+            rom_crc: crc.sum(),
+            save_path,
+            last_ram_write: None
+        };
+        cartridge.load_save()?;
+
+        Ok(cartridge)
         
     }
 
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn rom_crc(&self) -> u32 {
+        self.rom_crc
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.external_ram.len() as u32);
+        state.write_bytes(&self.external_ram);
+        self.mbc.save_state(state);
+    }
+
+    // The loaded RAM is flushed to the save file like any other RAM write,
+    // as long as it differs from the current one
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+
+        if state.read_u32()? as usize != self.external_ram.len() {
+            return Err(SaveStateError::InvalidDataError);
+        }
+        let ram = state.read_bytes(self.external_ram.len())?;
+        let modified = *ram != *self.external_ram;
+        self.external_ram.copy_from_slice(ram);
+        self.mbc.load_state(state)?;
+
+        if modified && self.save_path.is_some() {
+            self.last_ram_write = Some(Instant::now());
+        }
+        Ok(())
+    }
+
     pub fn read_rom(&self, addr: u16) -> u8 {
-        self.rom[addr as usize]
+        self.mbc.read_rom(&self.rom, addr)
     }
 
     pub fn write_rom(&mut self, addr: u16, data: u8) {
-
+        self.mbc.write_rom(addr, data);
     }
 
     pub fn read_ram(&self, addr: u16) -> u8 {
-        //self.external_ram[addr as usize]   // TODO: check there is ram
-        0xFF
+        self.mbc.read_ram(&self.external_ram, addr)
     }
 
     pub fn write_ram(&mut self, addr: u16, data: u8) {
-        self.external_ram[addr as usize] = data;   // TODO: check there is ram
+        let modified = self.mbc.write_ram(&mut self.external_ram, addr, data);
+        if modified && self.save_path.is_some() {
+            self.last_ram_write = Some(Instant::now());
+        }
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
     }
 
+    pub fn set_infrared_port(&mut self, port: Box<dyn InfraredPort>) {
+        self.mbc.set_infrared_port(port);
+    }
+
+}
+
+// MBC1M multicarts are 1 MiB MBC1 carts made of 256 KiB games, each one with
+// its own header, so the Nintendo logo shows up again at the start of bank 0x10
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    rom.len() == 0x100000 && rom[0x104 .. 0x134] == rom[0x40104 .. 0x40134]
+}
+
+fn has_battery(cart_type: u8) -> bool {
+    matches!(cart_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFE | 0xFF)
 }
 
 fn get_ext_ram_size(byte: u8) -> Option<usize> {
@@ -0,0 +1,37 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use super::Mbc;
+
+
+// Plain 32 KiB ROM, optionally with up to 8 KiB of RAM that is always mapped
+pub struct NoMbc;
+
+impl Mbc for NoMbc {
+
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        rom[addr as usize]
+    }
+
+    fn write_rom(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if ram.is_empty() {
+            return 0xFF;
+        }
+        ram[addr as usize % ram.len()]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if ram.is_empty() {
+            return false;
+        }
+        ram[addr as usize % ram.len()] = data;
+        true
+    }
+
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+
+}
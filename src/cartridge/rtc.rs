@@ -0,0 +1,168 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+
+// MBC3 real time clock. The clock keeps running from the host clock, so the
+// time also advances while the emulator isn't running as long as `last_update`
+// is persisted along with the registers
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+
+    latched: [u8; 5],
+    last_update: u64
+}
+
+impl Rtc {
+
+    pub fn new() -> Rtc {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            last_update: unix_time()
+        }
+    }
+
+    // Registers are selected with the RAM bank numbers 0x08 - 0x0C
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched[(reg - 0x08) as usize]
+    }
+
+    pub fn write(&mut self, reg: u8, data: u8) {
+        self.update();
+        match reg {
+            0x08 => self.seconds = data & 0x3F,
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | data as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((data as u16 & 0x01) << 8);
+                self.halted = data & 0x40 != 0;
+                self.day_carry = data & 0x80 != 0;
+            },
+            _ => {}
+        }
+    }
+
+    pub fn latch(&mut self) {
+        self.update();
+        self.latched = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            self.day_high()
+        ];
+    }
+
+    // Like the .sav footer, the time since `last_update` is added once the
+    // clock is accessed again
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.seconds, self.minutes, self.hours]);
+        state.write_u16(self.days);
+        state.write_bool(self.halted);
+        state.write_bool(self.day_carry);
+        state.write_bytes(&self.latched);
+        state.write_u64(self.last_update);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.seconds = state.read_u8()? & 0x3F;
+        self.minutes = state.read_u8()? & 0x3F;
+        self.hours = state.read_u8()? & 0x1F;
+        self.days = state.read_u16()? & 0x1FF;
+        self.halted = state.read_bool()?;
+        self.day_carry = state.read_bool()?;
+        state.read_into(&mut self.latched)?;
+        self.last_update = state.read_u64()?;
+        Ok(())
+    }
+
+    // Common .sav RTC footer: current and latched registers as 32 bit little
+    // endian values followed by the 64 bit timestamp of the last update
+    pub fn save(&self, out: &mut Vec<u8>) {
+        let current = [self.seconds, self.minutes, self.hours, self.days as u8, self.day_high()];
+        for reg in current.iter().chain(self.latched.iter()) {
+            out.extend_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        out.extend_from_slice(&self.last_update.to_le_bytes());
+    }
+
+    // Older emulators only store a 32 bit timestamp, so both the 44 and 48 byte
+    // footers are accepted
+    pub fn load(&mut self, data: &[u8]) {
+
+        if data.len() != 44 && data.len() != 48 {
+            return;
+        }
+
+        let reg = |idx: usize| data[idx * 4];
+
+        self.seconds = reg(0) & 0x3F;
+        self.minutes = reg(1) & 0x3F;
+        self.hours = reg(2) & 0x1F;
+        self.days = ((reg(4) as u16 & 0x01) << 8) | reg(3) as u16;
+        self.halted = reg(4) & 0x40 != 0;
+        self.day_carry = reg(4) & 0x80 != 0;
+        for idx in 0..5 {
+            self.latched[idx] = reg(idx + 5);
+        }
+
+        let mut timestamp = [0; 8];
+        timestamp[.. data.len() - 40].copy_from_slice(&data[40 ..]);
+        self.last_update = u64::from_le_bytes(timestamp);
+
+        // Catch up with the time that passed while the game wasn't running
+        self.update();
+    }
+
+    fn day_high(&self) -> u8 {
+        ((self.day_carry as u8) << 7) | ((self.halted as u8) << 6) | (self.days >> 8) as u8
+    }
+
+    fn update(&mut self) {
+        let now = unix_time();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        if !self.halted {
+            self.advance(elapsed);
+        }
+    }
+
+    fn advance(&mut self, secs: u64) {
+        let total = self.seconds as u64 + secs;
+        self.seconds = (total % 60) as u8;
+
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+
+        // The day counter is 9 bits wide, the carry bit stays set until cleared by the game
+        let total = self.days as u64 + total / 24;
+        if total > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (total % 0x200) as u16;
+    }
+
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,66 @@
+use std::{io::ErrorKind, time::Duration};
+
+use super::{Cartridge, CartridgeError};
+
+
+// Time without RAM writes after which the save file is flushed, so a game
+// writing its save data over several frames is only saved once
+const SAVE_DELAY: Duration = Duration::from_secs(3);
+
+impl Cartridge {
+
+    // The .sav file is the raw external RAM, followed by the RTC footer for MBC3 and HuC3 carts
+    pub(super) fn load_save(&mut self) -> Result<(), CartridgeError> {
+
+        let Some(save_path) = self.save_path.as_ref() else {
+            return Ok(());
+        };
+
+        let save = match std::fs::read(save_path) {
+            Ok(save) => save,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(_) => return Err(CartridgeError::SaveReadError)
+        };
+
+        let ram_size = self.external_ram.len().min(save.len());
+        self.external_ram[.. ram_size].copy_from_slice(&save[.. ram_size]);
+        if save.len() > self.external_ram.len() {
+            self.mbc.load_rtc(&save[self.external_ram.len() ..]);
+        }
+
+        Ok(())
+    }
+
+    pub fn save_ram(&mut self) -> std::io::Result<()> {
+
+        let Some(save_path) = self.save_path.as_ref() else {
+            return Ok(());
+        };
+
+        let mut save = self.external_ram.to_vec();
+        self.mbc.save_rtc(&mut save);
+        std::fs::write(save_path, save)?;
+
+        self.last_ram_write = None;
+        Ok(())
+    }
+
+    // Flushes the save file once the game has stopped writing to RAM for a while
+    pub fn autosave(&mut self) -> std::io::Result<()> {
+        match self.last_ram_write {
+            Some(last_write) if last_write.elapsed() >= SAVE_DELAY => self.save_ram(),
+            _ => Ok(())
+        }
+    }
+
+}
+
+impl Drop for Cartridge {
+
+    fn drop(&mut self) {
+        if self.last_ram_write.is_some() {
+            let _ = self.save_ram();
+        }
+    }
+
+}
@@ -1,59 +1,57 @@
-use super::{Cpu, registers::FlagMask, CpuState};
-use super::interrupts::InterruptMasterEnable;
+use super::{Cpu, registers::FlagMask, CpuState, SPEED_SWITCH_CYCLES};
+use super::interrupts::{InterruptMask, InterruptMasterEnable};
 use crate::mmu::Mmu;
 
 
 impl Cpu {
 
-    pub fn instructions(&mut self, mmu: &mut Mmu, instr: u8) -> u8 {
+    pub fn instructions(&mut self, mmu: &mut Mmu, instr: u8) {
         
         match instr {
 
             // NOP
-            0x00 => { 1 },
+            0x00 => {},
 
             // LD (u16), SP
             0x08 => {
-                let addr = mmu.read_word(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(2);
-                mmu.write_word(addr, self.regs.sp);
-                5
+                let addr = self.fetch_word(mmu);
+                self.write_cycle(mmu, addr, self.regs.sp as u8);
+                self.write_cycle(mmu, addr.wrapping_add(1), (self.regs.sp >> 8) as u8);
+            },
+
+            // STOP. On CGB with a speed switch armed in KEY1 it switches speed
+            // instead, pausing the CPU for a while
+            0x10 => {
+                self.fetch_byte(mmu);
+                if mmu.speed_switch_armed() {
+                    mmu.switch_speed();
+                    self.state = CpuState::SwitchingSpeed { remaining: SPEED_SWITCH_CYCLES };
+                } else {
+                    mmu.timer.reset_div();
+                    mmu.ppu.blank_screen();
+                    self.state = CpuState::Stopped;
+                }
             },
 
-            // STOP
-            0x10 => { 1 },
-
             // JR (unconditional)
             0x18 => {
-                let jump_len = mmu.read_byte(self.regs.pc) as i8;
-                self.regs.pc = self.regs.pc.wrapping_add(1);
+                let jump_len = self.fetch_byte(mmu) as i8;
+                self.internal_cycle(mmu);
                 self.regs.pc = self.regs.pc.wrapping_add_signed(jump_len as i16);
-                3
             },
 
             // JR (conditional)
             0x20 | 0x28 | 0x30 | 0x38 => {
-                let jump_len = mmu.read_byte(self.regs.pc) as i8;
-                self.regs.pc = self.regs.pc.wrapping_add(1);
-                let cond_met = match (instr >> 3) & 0x03 {
-                    0 => !self.regs.get_flag(FlagMask::Z),
-                    1 => self.regs.get_flag(FlagMask::Z),
-                    2 => !self.regs.get_flag(FlagMask::C),
-                    3 => self.regs.get_flag(FlagMask::C),
-                    _ => unreachable!()
-                };
-                if cond_met {
+                let jump_len = self.fetch_byte(mmu) as i8;
+                if self.condition(instr) {
+                    self.internal_cycle(mmu);
                     self.regs.pc = self.regs.pc.wrapping_add_signed(jump_len as i16);
-                    3
-                }  else {
-                    2
-                }   
+                }
             },  
 
             // LD r16, u16
             0x01 | 0x11 | 0x21 | 0x31 => {
-                let data = mmu.read_word(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(2);
+                let data = self.fetch_word(mmu);
                 match (instr >> 4) & 0x03 {
                     0 => self.regs.set_bc(data),
                     1 => self.regs.set_de(data),
@@ -61,7 +59,6 @@ impl Cpu {
                     3 => self.regs.sp = data,
                     _ => unreachable!()
                 }
-                3
             },
 
             // ADD HL, r16
@@ -77,58 +74,29 @@ impl Cpu {
 
                 let half_carry = ((hl & 0xFFF) + (r16 & 0xFFF)) >= (1 << 12);
                 let (hl, carry) = hl.overflowing_add(r16);
+                self.internal_cycle(mmu);
                 self.regs.set_hl(hl);
 
                 self.regs.set_flag_val(FlagMask::N, false);
                 self.regs.set_flag_val(FlagMask::H, half_carry);
                 self.regs.set_flag_val(FlagMask::C, carry);
-                2
             },
 
             // LD (r16), A
             0x02 | 0x12 | 0x22 | 0x32 => {
-                let addr = match (instr >> 4) & 0x03 {
-                    0 => self.regs.get_bc(),
-                    1 => self.regs.get_de(),
-                    2 => {
-                        let hl = self.regs.get_hl();
-                        self.regs.set_hl(hl.wrapping_add(1));
-                        hl
-                    },
-                    3 => {
-                        let hl = self.regs.get_hl();
-                        self.regs.set_hl(hl.wrapping_sub(1));
-                        hl
-                    },
-                    _ => unreachable!()
-                };
-                mmu.write_byte(addr, self.regs.a);
-                2
+                let addr = self.indirect_addr(instr);
+                self.write_cycle(mmu, addr, self.regs.a);
             },
 
             // LD A, (r16)
             0x0A | 0x1A | 0x2A | 0x3A => {
-                let addr = match (instr >> 4) & 0x03 {
-                    0 => self.regs.get_bc(),
-                    1 => self.regs.get_de(),
-                    2 => {
-                        let hl = self.regs.get_hl();
-                        self.regs.set_hl(hl.wrapping_add(1));
-                        hl
-                    },
-                    3 => {
-                        let hl = self.regs.get_hl();
-                        self.regs.set_hl(hl.wrapping_sub(1));
-                        hl
-                    },
-                    _ => unreachable!()
-                };
-                self.regs.a = mmu.read_byte(addr);
-                2
+                let addr = self.indirect_addr(instr);
+                self.regs.a = self.read_cycle(mmu, addr);
             },
 
             // INC r16
             0x03 | 0x13 | 0x23 | 0x33 => {
+                self.internal_cycle(mmu);
                 match (instr >> 4) & 0x3 {
                     0 => self.regs.set_bc(self.regs.get_bc().wrapping_add(1)),
                     1 => self.regs.set_de(self.regs.get_de().wrapping_add(1)),
@@ -136,11 +104,11 @@ impl Cpu {
                     3 => self.regs.sp = self.regs.sp.wrapping_add(1),
                     _ => unreachable!()
                 }
-                2
             },
 
             // DEC r16
             0x0B | 0x1B | 0x2B | 0x3B => {
+                self.internal_cycle(mmu);
                 match (instr >> 4) & 0x3 {
                     0 => self.regs.set_bc(self.regs.get_bc().wrapping_sub(1)),
                     1 => self.regs.set_de(self.regs.get_de().wrapping_sub(1)),
@@ -148,93 +116,36 @@ impl Cpu {
                     3 => self.regs.sp = self.regs.sp.wrapping_sub(1),
                     _ => unreachable!()
                 }
-                2
             },  
 
             // INC r8
             0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
-                let r8 = match (instr >> 3) & 0x7 {
-                    0 => self.regs.b,
-                    1 => self.regs.c,
-                    2 => self.regs.d,
-                    3 => self.regs.e,
-                    4 => self.regs.h,
-                    5 => self.regs.l,
-                    6 => mmu.read_byte(self.regs.get_hl()),
-                    7 => self.regs.a,
-                    _ => unreachable!()
-                };
-
+                let r8 = self.read_r8(mmu, instr >> 3);
                 let res = r8.wrapping_add(1);
 
                 self.regs.set_flag_val(FlagMask::Z, res == 0);
                 self.regs.clear_flag(FlagMask::N);
                 self.regs.set_flag_val(FlagMask::H, ((r8 & 0x0F) + (1 & 0x0F)) >= (1 << 4));
 
-                match (instr >> 3) & 0x7 {
-                    0 => { self.regs.b = res; 1},
-                    1 => { self.regs.c = res; 1 },
-                    2 => { self.regs.d = res; 1 },
-                    3 => { self.regs.e = res; 1 },
-                    4 => { self.regs.h = res; 1 },
-                    5 => { self.regs.l = res; 1 },
-                    6 => { mmu.write_byte(self.regs.get_hl(), res); 3 },
-                    7 => { self.regs.a = res; 1 },
-                    _ => unreachable!()
-                }
+                self.write_r8(mmu, instr >> 3, res);
             },
 
             // DEC r8
             0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D  => {
-                let mut r8 = match (instr >> 3) & 0x7 {
-                    0 => self.regs.b,
-                    1 => self.regs.c,
-                    2 => self.regs.d,
-                    3 => self.regs.e,
-                    4 => self.regs.h,
-                    5 => self.regs.l,
-                    6 => mmu.read_byte(self.regs.get_hl()),
-                    7 => self.regs.a,
-                    _ => unreachable!()
-                };
-
+                let r8 = self.read_r8(mmu, instr >> 3);
                 let res = r8.wrapping_sub(1);
 
                 self.regs.set_flag_val(FlagMask::Z, res == 0);
                 self.regs.set_flag(FlagMask::N);
-                self.regs.set_flag_val(FlagMask::H, !(r8 << 4).checked_add_signed(-1 << 4).is_some());
+                self.regs.set_flag_val(FlagMask::H, (r8 << 4).checked_add_signed(-1 << 4).is_none());
 
-                r8 = r8.wrapping_add(1);
-
-                match (instr >> 3) & 0x7 {
-                    0 => { self.regs.b = res; 1},
-                    1 => { self.regs.c = res; 1 },
-                    2 => { self.regs.d = res; 1 },
-                    3 => { self.regs.e = res; 1 },
-                    4 => { self.regs.h = res; 1 },
-                    5 => { self.regs.l = res; 1 },
-                    6 => { mmu.write_byte(self.regs.get_hl(), res); 3 },
-                    7 => { self.regs.a = res; 1 },
-                    _ => unreachable!()
-                }
+                self.write_r8(mmu, instr >> 3, res);
             },
 
             // LD r8, u8
             0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E  => {
-                let data = mmu.read_byte(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(1);
-                match (instr >> 3) & 0x7 {
-                    0 => self.regs.b = data,
-                    1 => self.regs.c = data,
-                    2 => self.regs.d = data,
-                    3 => self.regs.e = data,
-                    4 => self.regs.h = data,
-                    5 => self.regs.l = data,
-                    6 => mmu.write_byte(self.regs.get_hl(), data),
-                    7 => self.regs.a = data,
-                    _ => unreachable!()
-                }
-                2
+                let data = self.fetch_byte(mmu);
+                self.write_r8(mmu, instr >> 3, data);
             },
 
             // RLCA
@@ -247,7 +158,6 @@ impl Cpu {
 
                 self.regs.a <<= 1;
                 self.regs.a |= carry_flag as u8;
-                1
             },
 
             // RRCA
@@ -260,7 +170,6 @@ impl Cpu {
 
                 self.regs.a >>= 1;
                 self.regs.a |= (carry_flag as u8) << 7;
-                1
             },
 
             // RLA
@@ -273,7 +182,6 @@ impl Cpu {
 
                 self.regs.a <<= 1;
                 self.regs.a |= prev_carry_flag as u8;
-                1
             },
 
             // RRA
@@ -286,21 +194,16 @@ impl Cpu {
 
                 self.regs.a >>= 1;
                 self.regs.a |= (prev_carry_flag as u8) << 7;
-                1
             },
 
             // DAA
-            0x27 => {
-                self.daa();
-                1
-            },
+            0x27 => self.daa(),
 
             // CPL
             0x2F => {
                 self.regs.set_flag(FlagMask::N);
                 self.regs.set_flag(FlagMask::H);
                 self.regs.a = !self.regs.a;
-                1
             },
 
             // SCF
@@ -308,7 +211,6 @@ impl Cpu {
                 self.regs.clear_flag(FlagMask::N);
                 self.regs.clear_flag(FlagMask::H);
                 self.regs.set_flag(FlagMask::C);
-                1
             },
 
             // CCF
@@ -316,140 +218,82 @@ impl Cpu {
                 self.regs.clear_flag(FlagMask::N);
                 self.regs.clear_flag(FlagMask::H);
                 self.regs.set_flag_val(FlagMask::C, !self.regs.get_flag(FlagMask::C));
-                1
             },
 
-            // HALT
+            // HALT. With an interrupt already pending the CPU doesn't halt: with IME
+            // set it is dispatched right away, otherwise the HALT bug triggers.
+            // Right after EI IME isn't set yet, so `EI; HALT` also triggers it
             0x76 => {
-                self.state = CpuState::Halted;
-                1
+                let interrupt_pending = (mmu.interrupt_flag & mmu.interrupt_enable & InterruptMask::ALL) > 0;
+                if !interrupt_pending {
+                    self.state = CpuState::Halted;
+                } else if self.regs.ime != InterruptMasterEnable::Enabled {
+                    self.halt_bug = true;
+                }
             },
 
             // LD r8, r8
             0x40 ..= 0x75 | 0x77 ..= 0x7F => {
-                let r8_source = match instr & 0x7 {
-                    0 => self.regs.b,
-                    1 => self.regs.c,
-                    2 => self.regs.d,
-                    3 => self.regs.e,
-                    4 => self.regs.h,
-                    5 => self.regs.l,
-                    6 => mmu.read_byte(self.regs.get_hl()),
-                    7 => self.regs.a,
-                    _ => unreachable!()
-                };
-                let cycles = if instr & 0x7 == 6 { 2 } else { 1 };
-                match (instr >> 3) & 0x7 {
-                    0 => { self.regs.b = r8_source; cycles },
-                    1 => { self.regs.c = r8_source; cycles },
-                    2 => { self.regs.d = r8_source; cycles },
-                    3 => { self.regs.e = r8_source; cycles },
-                    4 => { self.regs.h = r8_source; cycles },
-                    5 => { self.regs.l = r8_source; cycles },
-                    6 => { mmu.write_byte(self.regs.get_hl(), r8_source); 2 },
-                    7 => { self.regs.a = r8_source; cycles },
-                    _ => unreachable!()
-                }
+                let r8_source = self.read_r8(mmu, instr);
+                self.write_r8(mmu, instr >> 3, r8_source);
             },
 
             // ALU A, r8
             0x80 ..= 0xBF => {
-                let r8 = match instr & 0x7 {
-                    0 => self.regs.b,
-                    1 => self.regs.c,
-                    2 => self.regs.d,
-                    3 => self.regs.e,
-                    4 => self.regs.h,
-                    5 => self.regs.l,
-                    6 => mmu.read_byte(self.regs.get_hl()),
-                    7 => self.regs.a,
-                    _ => unreachable!()
-                };
-
-                match (instr >> 3) & 0x7 {
-                    0 => self.add_u8(r8),
-                    1 => self.adc_u8(r8),
-                    2 => self.sub_u8(r8),
-                    3 => self.sbc_u8(r8),
-                    4 => self.and_u8(r8),
-                    5 => self.xor_u8(r8),
-                    6 => self.or_u8(r8),
-                    7 => self.cp_u8(r8),
-                    _ => unreachable!()
-                }
-
-                if (instr & 0x7) == 6 {
-                    2
-                } else {
-                    1
-                }
+                let r8 = self.read_r8(mmu, instr);
+                self.alu(instr, r8);
             },
 
             // RET condition
             0xC0 | 0xC8 | 0xD0 | 0xD8 => {
-                let cond_met = match (instr >> 3) & 0x03 {
-                    0 => !self.regs.get_flag(FlagMask::Z),
-                    1 => self.regs.get_flag(FlagMask::Z),
-                    2 => !self.regs.get_flag(FlagMask::C),
-                    3 => self.regs.get_flag(FlagMask::C),
-                    _ => unreachable!()
-                };
-                if cond_met {
+                self.internal_cycle(mmu);
+                if self.condition(instr) {
                     self.regs.pc = self.stack_pop(mmu);
-                    5
-                }  else {
-                    2
-                }   
+                    self.internal_cycle(mmu);
+                }
             },
 
             // LD (FF00 + u8), A
             0xE0 => {
-                let addr = mmu.read_byte(self.regs.pc) as u16 | 0xFF00;
-                self.regs.pc = self.regs.pc.wrapping_add(1);
-                mmu.write_byte(addr, self.regs.a);
-                3
+                let addr = self.fetch_byte(mmu) as u16 | 0xFF00;
+                self.write_cycle(mmu, addr, self.regs.a);
             },
 
             // ADD SP, i8
             0xE8 => {
-                let r8 = mmu.read_byte(self.regs.pc) as i8 as i16 as u16;
-                self.regs.pc = self.regs.pc.wrapping_add(1);
+                let r8 = self.fetch_byte(mmu) as i8 as i16 as u16;
 
                 let carry = (self.regs.sp & 0xFF) + (r8 & 0xFF) > 0xFF;
                 let half_carry = (self.regs.sp & 0xF) + (r8 & 0xF) > 0xF;
+                self.internal_cycle(mmu);
+                self.internal_cycle(mmu);
                 self.regs.sp = self.regs.sp.wrapping_add(r8);
 
                 self.regs.clear_flag(FlagMask::Z);
                 self.regs.clear_flag(FlagMask::N);
                 self.regs.set_flag_val(FlagMask::H, half_carry);
                 self.regs.set_flag_val(FlagMask::C, carry);
-
-                4
             },
 
             // LD A, (FF00 + u8)
             0xF0 => {
-                let addr = mmu.read_byte(self.regs.pc) as u16 | 0xFF00;
-                self.regs.pc = self.regs.pc.wrapping_add(1);
-                self.regs.a = mmu.read_byte(addr);
-                3
+                let addr = self.fetch_byte(mmu) as u16 | 0xFF00;
+                self.regs.a = self.read_cycle(mmu, addr);
             },
 
             // LD HL, SP + i8
             0xF8 => {
-                let r8 = mmu.read_byte(self.regs.pc) as i8 as i16 as u16;
-                self.regs.pc = self.regs.pc.wrapping_add(1);
+                let r8 = self.fetch_byte(mmu) as i8 as i16 as u16;
 
                 let carry = (self.regs.sp & 0xFF) + (r8 & 0xFF) > 0xFF;
                 let half_carry = (self.regs.sp & 0xF) + (r8 & 0xF) > 0xF;
+                self.internal_cycle(mmu);
                 self.regs.set_hl(self.regs.sp.wrapping_add(r8));
 
                 self.regs.clear_flag(FlagMask::Z);
                 self.regs.clear_flag(FlagMask::N);
                 self.regs.set_flag_val(FlagMask::H, half_carry);
                 self.regs.set_flag_val(FlagMask::C, carry);
-
-                3
             },
 
             // POP r16
@@ -462,200 +306,142 @@ impl Cpu {
                     3 => self.regs.set_af(data),
                     _ => unreachable!()
                 }
-                3
             },
 
             // RET
             0xC9 => {
                 self.regs.pc = self.stack_pop(mmu);
-                4
+                self.internal_cycle(mmu);
             },
 
             // RETI
             0xD9 => {
                 self.regs.pc = self.stack_pop(mmu);
+                self.internal_cycle(mmu);
                 self.regs.ime = InterruptMasterEnable::Enabled;
-                4
             },
 
             // JP HL
-            0xE9 => {
-                self.regs.pc = self.regs.get_hl();
-                1
-            },
+            0xE9 => self.regs.pc = self.regs.get_hl(),
 
             // LD SP, HL
             0xF9 => {
+                self.internal_cycle(mmu);
                 self.regs.sp = self.regs.get_hl();
-                2
             },
 
             // JP condition
             0xC2 | 0xCA | 0xD2 | 0xDA => {
-                let jump_addr = mmu.read_word(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(2);
-                let cond_met = match (instr >> 3) & 0x03 {
-                    0 => !self.regs.get_flag(FlagMask::Z),
-                    1 => self.regs.get_flag(FlagMask::Z),
-                    2 => !self.regs.get_flag(FlagMask::C),
-                    3 => self.regs.get_flag(FlagMask::C),
-                    _ => unreachable!()
-                };
-                if cond_met {
+                let jump_addr = self.fetch_word(mmu);
+                if self.condition(instr) {
+                    self.internal_cycle(mmu);
                     self.regs.pc = jump_addr;
-                    4
-                } else {
-                    3
                 }
             },
 
             // LD (FF00+C), A
             0xE2 => {
                 let addr = self.regs.c as u16 | 0xFF00;
-                mmu.write_byte(addr, self.regs.a);
-                2
+                self.write_cycle(mmu, addr, self.regs.a);
             },
 
             // LD (u16), A
             0xEA => {
-                let addr = mmu.read_word(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(2);
-                mmu.write_byte(addr, self.regs.a);
-                4
+                let addr = self.fetch_word(mmu);
+                self.write_cycle(mmu, addr, self.regs.a);
             },
 
             // LD A, (0xFF00+C)
             0xF2 => {
                 let addr = self.regs.c as u16 | 0xFF00;
-                self.regs.a = mmu.read_byte(addr);
-                2
+                self.regs.a = self.read_cycle(mmu, addr);
             },
 
             // LD A, (u16)
             0xFA => {
-                let addr = mmu.read_word(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(2);
-                self.regs.a = mmu.read_byte(addr);
-                4
+                let addr = self.fetch_word(mmu);
+                self.regs.a = self.read_cycle(mmu, addr);
             },
 
             // JP u16
             0xC3 => {
-                self.regs.pc = mmu.read_word(self.regs.pc);
-                4
+                let jump_addr = self.fetch_word(mmu);
+                self.internal_cycle(mmu);
+                self.regs.pc = jump_addr;
             },
 
             // CB prefix
             0xCB => {
-                let instr = mmu.read_byte(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(1);
-                self.cb_instructions(mmu, instr)
+                let instr = self.fetch_byte(mmu);
+                self.cb_instructions(mmu, instr);
             },
 
             // DI
-            0xF3 => {
-                self.regs.ime = InterruptMasterEnable::Disabled;
-                1
-            },
+            0xF3 => self.regs.ime = InterruptMasterEnable::Disabled,
 
             // EI
-            0xFB => {
-                self.regs.ime = InterruptMasterEnable::EnabledWithDelay;
-                1
-            },
+            0xFB => self.regs.ime = InterruptMasterEnable::EnabledWithDelay,
 
             // CALL condition
             0xC4 | 0xCC | 0xD4 | 0xDC => {
-                let call_addr = mmu.read_word(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(2);
-                let cond_met = match (instr >> 3) & 0x03 {
-                    0 => !self.regs.get_flag(FlagMask::Z),
-                    1 => self.regs.get_flag(FlagMask::Z),
-                    2 => !self.regs.get_flag(FlagMask::C),
-                    3 => self.regs.get_flag(FlagMask::C),
-                    _ => unreachable!()
-                };
-                if cond_met {
+                let call_addr = self.fetch_word(mmu);
+                if self.condition(instr) {
+                    self.internal_cycle(mmu);
                     self.stack_push(mmu, self.regs.pc);
                     self.regs.pc = call_addr;
-                    6
-                } else {
-                    3
                 }
             },
 
             // PUSH r16
             0xC5 | 0xD5 | 0xE5 | 0xF5 => {
-                match (instr >> 4) & 0x3 {
-                    0 => self.stack_push(mmu, self.regs.get_bc()),
-                    1 => self.stack_push(mmu, self.regs.get_de()),
-                    2 => self.stack_push(mmu, self.regs.get_hl()),
-                    3 => self.stack_push(mmu, self.regs.get_af()),
+                let data = match (instr >> 4) & 0x3 {
+                    0 => self.regs.get_bc(),
+                    1 => self.regs.get_de(),
+                    2 => self.regs.get_hl(),
+                    3 => self.regs.get_af(),
                     _ => unreachable!()
-                }
-                4
+                };
+                self.internal_cycle(mmu);
+                self.stack_push(mmu, data);
             },
 
             // CALL u16
             0xCD => {
-                let call_addr = mmu.read_word(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(2);
+                let call_addr = self.fetch_word(mmu);
+                self.internal_cycle(mmu);
                 self.stack_push(mmu, self.regs.pc);
                 self.regs.pc = call_addr;
-                6
             },
 
             // ALU a, u8
             0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE  => {
-                let r8 = mmu.read_byte(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(1);
-
-                match (instr >> 3) & 0x7 {
-                    0 => self.add_u8(r8),
-                    1 => self.adc_u8(r8),
-                    2 => self.sub_u8(r8),
-                    3 => self.sbc_u8(r8),
-                    4 => self.and_u8(r8),
-                    5 => self.xor_u8(r8),
-                    6 => self.or_u8(r8),
-                    7 => self.cp_u8(r8),
-                    _ => unreachable!()
-                }
-
-                2
+                let r8 = self.fetch_byte(mmu);
+                self.alu(instr, r8);
             },
 
             // RST (Call to 00EXP000)
             0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF  => {
                 let interrupt_addr = ((instr >> 3) & 0x7) * 0x08;
+                self.internal_cycle(mmu);
                 self.stack_push(mmu, self.regs.pc);
                 self.regs.pc = interrupt_addr as u16;
-                4
             },
 
-            // Illegal instructions
-            _ => unreachable!()
+            // Illegal instructions (0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD)
+            _ => {
+                self.state = CpuState::Locked { opcode: instr, addr: self.regs.pc.wrapping_sub(1) };
+            }
         }
 
     }
 
-    fn cb_instructions(&mut self, mmu: &mut Mmu, instr: u8) -> u8 {
+    fn cb_instructions(&mut self, mmu: &mut Mmu, instr: u8) {
 
         match (instr >> 6) & 0x3 {
 
             0x0 => {
 
-                let mut operand = match instr & 0x7 {
-                    0 => self.regs.b,
-                    1 => self.regs.c,
-                    2 => self.regs.d,
-                    3 => self.regs.e,
-                    4 => self.regs.h,
-                    5 => self.regs.l,
-                    6 => mmu.read_byte(self.regs.get_hl()),
-                    7 => self.regs.a,
-                    _ => unreachable!()
-                };
+                let mut operand = self.read_r8(mmu, instr);
 
                 match (instr >> 3) & 0x7 {
                     0 => self.rlc_u8(&mut operand),
@@ -669,74 +455,28 @@ impl Cpu {
                     _ => unreachable!()
                 }
         
-                match instr & 0x7 {
-                    0 => { self.regs.b = operand; 2 },
-                    1 => { self.regs.c = operand; 2 },
-                    2 => { self.regs.d = operand; 2 },
-                    3 => { self.regs.e = operand; 2 },
-                    4 => { self.regs.h = operand; 2 },
-                    5 => { self.regs.l = operand; 2 },
-                    6 => { mmu.write_byte(self.regs.get_hl(), operand); 4 },
-                    7 => { self.regs.a = operand; 2 },
-                    _ => unreachable!()
-                }
-
+                self.write_r8(mmu, instr, operand);
             },
 
             // BIT bit, r8
             1 => {
                 let bit = (instr >> 3) & 0x7;
-                match instr & 0x7 {
-                    0 => { self.bit(self.regs.b, bit); 2 },
-                    1 => { self.bit(self.regs.c, bit); 2 },
-                    2 => { self.bit(self.regs.d, bit); 2 },
-                    3 => { self.bit(self.regs.e, bit); 2 },
-                    4 => { self.bit(self.regs.h, bit); 2 },
-                    5 => { self.bit(self.regs.l, bit); 2 },
-                    6 => { self.bit(mmu.read_byte(self.regs.get_hl()), bit); 3 },
-                    7 => { self.bit(self.regs.a, bit); 2 },
-                    _ => unreachable!()
-                }
+                let operand = self.read_r8(mmu, instr);
+                self.bit(operand, bit);
             },
 
             // RES bit, r8
             2 => {
                 let mask = !(1 << ((instr >> 3) & 0x7));
-                match instr & 0x7 {
-                    0 => { self.regs.b &= mask; 2 },
-                    1 => { self.regs.c &= mask; 2 },
-                    2 => { self.regs.d &= mask; 2 },
-                    3 => { self.regs.e &= mask; 2 },
-                    4 => { self.regs.h &= mask; 2 },
-                    5 => { self.regs.l &= mask; 2 },
-                    6 => {
-                        let hl = self.regs.get_hl();
-                        mmu.write_byte(hl, mmu.read_byte(hl) & mask);
-                        3
-                    },
-                    7 => { self.regs.a &= mask; 2 },
-                    _ => unreachable!()
-                }
+                let operand = self.read_r8(mmu, instr);
+                self.write_r8(mmu, instr, operand & mask);
             },
 
             // SET bit, r8
             3 => {
                 let mask = 1 << ((instr >> 3) & 0x7);
-                match instr & 0x7 {
-                    0 => { self.regs.b |= mask; 2 },
-                    1 => { self.regs.c |= mask; 2 },
-                    2 => { self.regs.d |= mask; 2 },
-                    3 => { self.regs.e |= mask; 2 },
-                    4 => { self.regs.h |= mask; 2 },
-                    5 => { self.regs.l |= mask; 2 },
-                    6 => {
-                        let hl = self.regs.get_hl();
-                        mmu.write_byte(hl, mmu.read_byte(hl) | mask);
-                        3
-                    },
-                    7 => { self.regs.a |= mask; 2 },
-                    _ => unreachable!()
-                }
+                let operand = self.read_r8(mmu, instr);
+                self.write_r8(mmu, instr, operand | mask);
             },
 
             _ => unreachable!()
@@ -744,15 +484,93 @@ impl Cpu {
     
     }
 
+    // 8 bit register operand encoded in the lower 3 bits, 6 is (HL)
+    fn read_r8(&mut self, mmu: &mut Mmu, reg: u8) -> u8 {
+        match reg & 0x7 {
+            0 => self.regs.b,
+            1 => self.regs.c,
+            2 => self.regs.d,
+            3 => self.regs.e,
+            4 => self.regs.h,
+            5 => self.regs.l,
+            6 => self.read_cycle(mmu, self.regs.get_hl()),
+            7 => self.regs.a,
+            _ => unreachable!()
+        }
+    }
+
+    fn write_r8(&mut self, mmu: &mut Mmu, reg: u8, data: u8) {
+        match reg & 0x7 {
+            0 => self.regs.b = data,
+            1 => self.regs.c = data,
+            2 => self.regs.d = data,
+            3 => self.regs.e = data,
+            4 => self.regs.h = data,
+            5 => self.regs.l = data,
+            6 => self.write_cycle(mmu, self.regs.get_hl(), data),
+            7 => self.regs.a = data,
+            _ => unreachable!()
+        }
+    }
+
+    // (BC), (DE), (HL+) and (HL-) operands of the LD A instructions
+    fn indirect_addr(&mut self, instr: u8) -> u16 {
+        match (instr >> 4) & 0x03 {
+            0 => self.regs.get_bc(),
+            1 => self.regs.get_de(),
+            2 => {
+                let hl = self.regs.get_hl();
+                self.regs.set_hl(hl.wrapping_add(1));
+                hl
+            },
+            3 => {
+                let hl = self.regs.get_hl();
+                self.regs.set_hl(hl.wrapping_sub(1));
+                hl
+            },
+            _ => unreachable!()
+        }
+    }
+
+    // NZ, Z, NC and C conditions of the jump, call and return instructions
+    fn condition(&self, instr: u8) -> bool {
+        match (instr >> 3) & 0x03 {
+            0 => !self.regs.get_flag(FlagMask::Z),
+            1 => self.regs.get_flag(FlagMask::Z),
+            2 => !self.regs.get_flag(FlagMask::C),
+            3 => self.regs.get_flag(FlagMask::C),
+            _ => unreachable!()
+        }
+    }
+
+    fn alu(&mut self, instr: u8, val: u8) {
+        match (instr >> 3) & 0x7 {
+            0 => self.add_u8(val),
+            1 => self.adc_u8(val),
+            2 => self.sub_u8(val),
+            3 => self.sbc_u8(val),
+            4 => self.and_u8(val),
+            5 => self.xor_u8(val),
+            6 => self.or_u8(val),
+            7 => self.cp_u8(val),
+            _ => unreachable!()
+        }
+    }
+
+    // The high byte is pushed first
     pub fn stack_push(&mut self, mmu: &mut Mmu, data: u16) {
-        self.regs.sp = self.regs.sp.wrapping_sub(2);
-        mmu.write_word(self.regs.sp, data);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write_cycle(mmu, self.regs.sp, (data >> 8) as u8);
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write_cycle(mmu, self.regs.sp, data as u8);
     }
 
-    fn stack_pop(&mut self, mmu: &Mmu) -> u16 {
-        let word = mmu.read_word(self.regs.sp);
-        self.regs.sp = self.regs.sp.wrapping_add(2);
-        word
+    fn stack_pop(&mut self, mmu: &mut Mmu) -> u16 {
+        let low = self.read_cycle(mmu, self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        let high = self.read_cycle(mmu, self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(1);
+        (high as u16) << 8 | low as u16
     }
 
     fn add_u8(&mut self, val: u8) {
@@ -780,7 +598,7 @@ impl Cpu {
 
     fn sub_u8(&mut self, val: u8) {
         let (res, carry) = self.regs.a.overflowing_sub(val);
-        let half_carry = !(self.regs.a << 4).checked_sub(val << 4).is_some();
+        let half_carry = (self.regs.a << 4).checked_sub(val << 4).is_none();
         self.regs.a = res;
 
         self.regs.set_flag_val(FlagMask::Z, self.regs.a == 0);
@@ -13,31 +13,57 @@ pub enum InterruptMasterEnable {
 
 #[repr(u8)]
 pub enum InterruptMask {
-    Timer = 0x04
+    VBlank = 0x01,
+    Stat = 0x02,
+    Timer = 0x04,
+    Serial = 0x08,
+    Joypad = 0x10
+}
+
+impl InterruptMask {
+
+    // Only the low 5 bits of IF and IE belong to interrupts
+    pub const ALL: u8 = InterruptMask::VBlank as u8 | InterruptMask::Stat as u8 | InterruptMask::Timer as u8
+        | InterruptMask::Serial as u8 | InterruptMask::Joypad as u8;
+
 }
 
 impl Cpu {
     
-    pub fn handle_interrupts(&mut self, mmu: &mut Mmu) -> u8 {
+    // Takes 5 M-cycles: two internal ones, pushing PC and jumping to the vector.
+    // The vector is picked after pushing the high byte of PC, so if that push
+    // overwrites IE (SP = 0x0000) and clears the pending interrupt, the
+    // dispatch is cancelled and jumps to 0x0000 without acknowledging it
+    pub fn handle_interrupts(&mut self, mmu: &mut Mmu) {
 
-        let mut mask: u8 = 1;
+        self.regs.ime = InterruptMasterEnable::Disabled;
 
-        for addr in INTERR_ADDRESSES {
+        // After `EI; HALT` the HALT bug makes the interrupt return to the HALT itself
+        let mut return_addr = self.regs.pc;
+        if self.halt_bug {
+            self.halt_bug = false;
+            return_addr = return_addr.wrapping_sub(1);
+        }
 
-            if (mmu.interrupt_flag & mmu.interrupt_enable & mask) > 0 {
+        self.internal_cycle(mmu);
+        self.internal_cycle(mmu);
 
-                self.stack_push(mmu, self.regs.pc);
-                self.regs.pc = addr;
-                self.regs.ime = InterruptMasterEnable::Disabled;
-                mmu.interrupt_flag &= !mask;
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write_cycle(mmu, self.regs.sp, (return_addr >> 8) as u8);
 
-                return 5;
-            }
+        let pending = mmu.interrupt_flag & mmu.interrupt_enable & InterruptMask::ALL;
+        self.regs.pc = match pending.trailing_zeros() as usize {
+            idx if idx < INTERR_ADDRESSES.len() => {
+                mmu.interrupt_flag &= !(1 << idx);
+                INTERR_ADDRESSES[idx]
+            },
+            _ => 0x0000
+        };
 
-            mask <<= 1;
-        }
+        self.regs.sp = self.regs.sp.wrapping_sub(1);
+        self.write_cycle(mmu, self.regs.sp, return_addr as u8);
 
-        0
+        self.internal_cycle(mmu);
     }
 
 }
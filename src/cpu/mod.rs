@@ -6,17 +6,39 @@ mod interrupts;
 pub use self::interrupts::InterruptMask;
 
 use crate::mmu::Mmu;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 use self::{registers::Registers, interrupts::InterruptMasterEnable};
 
 
+// M-cycles the CPU is paused for after a CGB speed switch
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+
 enum CpuState {
     Running,
-    Halted
+    Halted,
+    Stopped,
+    SwitchingSpeed { remaining: u16 },
+    Locked { opcode: u8, addr: u16 }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CpuStatus {
+    Running,
+    Halted,
+    // STOP was executed, the system clock is stopped until a button is pressed
+    Stopped,
+    // STOP switched the CGB speed, the CPU idles while the rest of the system keeps running
+    SwitchingSpeed,
+    // An illegal opcode was executed, the CPU hangs until the console is reset
+    Locked { opcode: u8, addr: u16 }
 }
 
 pub struct Cpu {
     regs: Registers,
-    state: CpuState
+    state: CpuState,
+    halt_bug: bool,
+    // M-cycles taken by the current run_instruction() call
+    cycles: u8
 }
 
 impl Cpu {
@@ -24,55 +46,197 @@ impl Cpu {
     pub fn new() -> Cpu {
         Cpu {
             regs: Registers::new(),
-            state: CpuState::Running
+            state: CpuState::Running,
+            halt_bug: false,
+            cycles: 0
         }
     }
 
+    pub fn status(&self) -> CpuStatus {
+        match self.state {
+            CpuState::Running => CpuStatus::Running,
+            CpuState::Halted => CpuStatus::Halted,
+            CpuState::Stopped => CpuStatus::Stopped,
+            CpuState::SwitchingSpeed { .. } => CpuStatus::SwitchingSpeed,
+            CpuState::Locked { opcode, addr } => CpuStatus::Locked { opcode, addr }
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+
+        let regs = &self.regs;
+        state.write_bytes(&[regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l]);
+        state.write_u16(regs.pc);
+        state.write_u16(regs.sp);
+        state.write_u8(match regs.ime {
+            InterruptMasterEnable::Disabled => 0,
+            InterruptMasterEnable::Enabled => 1,
+            InterruptMasterEnable::EnabledWithDelay => 2
+        });
+
+        match self.state {
+            CpuState::Running => state.write_u8(0),
+            CpuState::Halted => state.write_u8(1),
+            CpuState::Stopped => state.write_u8(3),
+            CpuState::SwitchingSpeed { remaining } => {
+                state.write_u8(4);
+                state.write_u16(remaining);
+            },
+            CpuState::Locked { opcode, addr } => {
+                state.write_u8(2);
+                state.write_u8(opcode);
+                state.write_u16(addr);
+            }
+        }
+        state.write_bool(self.halt_bug);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+
+        let mut regs = [0; 8];
+        state.read_into(&mut regs)?;
+        let [a, f, b, c, d, e, h, l] = regs;
+        self.regs.a = a;
+        self.regs.f = f & 0xF0;
+        self.regs.b = b;
+        self.regs.c = c;
+        self.regs.d = d;
+        self.regs.e = e;
+        self.regs.h = h;
+        self.regs.l = l;
+        self.regs.pc = state.read_u16()?;
+        self.regs.sp = state.read_u16()?;
+        self.regs.ime = match state.read_u8()? {
+            0 => InterruptMasterEnable::Disabled,
+            1 => InterruptMasterEnable::Enabled,
+            2 => InterruptMasterEnable::EnabledWithDelay,
+            _ => return Err(SaveStateError::InvalidDataError)
+        };
+
+        self.state = match state.read_u8()? {
+            0 => CpuState::Running,
+            1 => CpuState::Halted,
+            2 => CpuState::Locked { opcode: state.read_u8()?, addr: state.read_u16()? },
+            3 => CpuState::Stopped,
+            4 => CpuState::SwitchingSpeed { remaining: state.read_u16()? },
+            _ => return Err(SaveStateError::InvalidDataError)
+        };
+        self.halt_bug = state.read_bool()?;
+
+        Ok(())
+    }
+
+    // Runs a single instruction, interrupt dispatch or idle cycle and returns
+    // the M-cycles it took. The rest of the system is ticked as they happen
     pub fn run_instruction(&mut self, mmu: &mut Mmu) -> u8 {
 
+        self.cycles = 0;
+        let interrupt_pending = (mmu.interrupt_flag & mmu.interrupt_enable & InterruptMask::ALL) > 0;
+
         match self.state {
 
-            CpuState::Halted => {
+            // Not even interrupts can wake the CPU up, but the rest of the system keeps running
+            CpuState::Locked { .. } => {
+                self.internal_cycle(mmu);
+                return self.cycles;
+            },
 
-                // TODO: Implement HALT BUG
+            // Any pending interrupt wakes the CPU up, even with IME disabled.
+            // Waking up takes an extra M-cycle before resuming execution
+            CpuState::Halted => {
+                self.internal_cycle(mmu);
+                if !interrupt_pending {
+                    return self.cycles;
+                }
+                self.state = CpuState::Running;
+            },
 
-                // If there is an interrupt to serve
-                if (mmu.interrupt_flag & 0x1F & mmu.interrupt_enable) > 0 {
-                    self.state = CpuState::Running;
-                    return 0;
-                } else {
-                    return 1;
+            // Only a selected joypad line going low ends STOP
+            CpuState::Stopped => {
+                if !mmu.joypad.input_active() {
+                    mmu.tick_stopped(1);
+                    self.cycles += 1;
+                    return self.cycles;
                 }
+                self.state = CpuState::Running;
+            },
 
+            CpuState::SwitchingSpeed { remaining } => {
+                self.internal_cycle(mmu);
+                self.state = match remaining {
+                    1 => CpuState::Running,
+                    _ => CpuState::SwitchingSpeed { remaining: remaining - 1 }
+                };
+                return self.cycles;
             },
 
-            CpuState::Running => {
+            CpuState::Running => {}
 
-                if self.regs.ime == InterruptMasterEnable::Enabled {
+        }
 
-                    // If there is an interrupt to serve
-                    if (mmu.interrupt_flag & 0x1F & mmu.interrupt_enable) > 0 {
-                        return self.handle_interrupts(mmu);
-                    }
-    
-                } else if self.regs.ime == InterruptMasterEnable::EnabledWithDelay {
-                    self.regs.ime = InterruptMasterEnable::Enabled;
-                }
+        // If there is an interrupt to serve
+        if self.regs.ime == InterruptMasterEnable::Enabled && interrupt_pending {
+            self.handle_interrupts(mmu);
+            return self.cycles;
+        }
 
-                /*println!("A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: 00:{:04X} ({:02X} {:02X} {:02X} {:02X})",
-                    self.regs.a, self.regs.f, self.regs.b, self.regs.c, self.regs.d, self.regs.e, self.regs.h, self.regs.l, self.regs.sp, self.regs.pc,
-                    mmu.read_byte(self.regs.pc), mmu.read_byte(self.regs.pc.wrapping_add(1)), mmu.read_byte(self.regs.pc.wrapping_add(2)), mmu.read_byte(self.regs.pc.wrapping_add(3))
-                );*/
+        // EI takes effect once the following instruction has finished, so
+        // e.g. in `EI; DI` interrupts are never enabled
+        let enable_interrupts = self.regs.ime == InterruptMasterEnable::EnabledWithDelay;
 
-                let instr = mmu.read_byte(self.regs.pc);
-                self.regs.pc = self.regs.pc.wrapping_add(1);
+        /*println!("A: {:02X} F: {:02X} B: {:02X} C: {:02X} D: {:02X} E: {:02X} H: {:02X} L: {:02X} SP: {:04X} PC: 00:{:04X} ({:02X} {:02X} {:02X} {:02X})",
+            self.regs.a, self.regs.f, self.regs.b, self.regs.c, self.regs.d, self.regs.e, self.regs.h, self.regs.l, self.regs.sp, self.regs.pc,
+            mmu.read_byte(self.regs.pc), mmu.read_byte(self.regs.pc.wrapping_add(1)), mmu.read_byte(self.regs.pc.wrapping_add(2)), mmu.read_byte(self.regs.pc.wrapping_add(3))
+        );*/
 
-                return self.instructions(mmu, instr);
-            }
+        let instr = self.fetch_byte(mmu);
+
+        // HALT bug: PC fails to increment after the opcode fetch, so the
+        // byte following HALT is read twice
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.regs.pc = self.regs.pc.wrapping_sub(1);
+        }
+
+        self.instructions(mmu, instr);
 
+        if enable_interrupts && self.regs.ime == InterruptMasterEnable::EnabledWithDelay {
+            self.regs.ime = InterruptMasterEnable::Enabled;
         }
 
-        0
+        self.cycles
+    }
+
+    // Every bus access takes one M-cycle. The rest of the system advances
+    // through that cycle first, so the access sees the timer, PPU and APU
+    // as they are at the moment it happens
+    fn read_cycle(&mut self, mmu: &mut Mmu, addr: u16) -> u8 {
+        self.internal_cycle(mmu);
+        mmu.read_byte(addr)
+    }
+
+    fn write_cycle(&mut self, mmu: &mut Mmu, addr: u16, data: u8) {
+        self.internal_cycle(mmu);
+        mmu.write_byte(addr, data);
+    }
+
+    // M-cycle without a bus access, e.g. 16 bit arithmetic or taking a jump
+    fn internal_cycle(&mut self, mmu: &mut Mmu) {
+        mmu.tick_components(1);
+        self.cycles += 1;
+    }
+
+    fn fetch_byte(&mut self, mmu: &mut Mmu) -> u8 {
+        let data = self.read_cycle(mmu, self.regs.pc);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        data
+    }
+
+    // Immediate words are stored little endian
+    fn fetch_word(&mut self, mmu: &mut Mmu) -> u16 {
+        let low = self.fetch_byte(mmu);
+        let high = self.fetch_byte(mmu);
+        (high as u16) << 8 | low as u16
     }
 
 }
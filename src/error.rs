@@ -0,0 +1,40 @@
+use std::fmt;
+
+use crate::cartridge::CartridgeError;
+use crate::savestate::SaveStateError;
+
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    Cartridge(CartridgeError),
+    SaveState(SaveStateError)
+}
+
+impl fmt::Display for EmulatorError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmulatorError::Cartridge(err) => write!(f, "cartridge error: {}", err),
+            EmulatorError::SaveState(err) => write!(f, "save state error: {}", err)
+        }
+    }
+
+}
+
+impl std::error::Error for EmulatorError {}
+
+impl From<CartridgeError> for EmulatorError {
+
+    fn from(err: CartridgeError) -> EmulatorError {
+        EmulatorError::Cartridge(err)
+    }
+
+}
+
+impl From<SaveStateError> for EmulatorError {
+
+    fn from(err: SaveStateError) -> EmulatorError {
+        EmulatorError::SaveState(err)
+    }
+
+}
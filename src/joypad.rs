@@ -0,0 +1,125 @@
+use crate::cpu::InterruptMask;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start
+}
+
+impl Button {
+
+    pub fn from_name(name: &str) -> Option<Button> {
+        match name.to_ascii_lowercase().as_str() {
+            "right" => Some(Button::Right),
+            "left" => Some(Button::Left),
+            "up" => Some(Button::Up),
+            "down" => Some(Button::Down),
+            "a" => Some(Button::A),
+            "b" => Some(Button::B),
+            "select" => Some(Button::Select),
+            "start" => Some(Button::Start),
+            _ => None
+        }
+    }
+
+    // Bit in the pressed buttons mask: low nibble for the direction keys and
+    // high nibble for the action buttons, both in P1 bit order
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80
+        }
+    }
+
+}
+
+pub struct Joypad {
+    select: u8,
+    pressed: u8
+}
+
+impl Joypad {
+
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0x30,
+            pressed: 0x00
+        }
+    }
+
+    // The held buttons come from the host and aren't part of the state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.select);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.select = state.read_u8()? & 0x30;
+        Ok(())
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.input_lines()
+    }
+
+    pub fn write(&mut self, data: u8, interrupt_flag: &mut u8) {
+        let prev_lines = self.input_lines();
+        self.select = data & 0x30;
+        self.check_interrupt(prev_lines, interrupt_flag);
+    }
+
+    pub fn press(&mut self, button: Button, interrupt_flag: &mut u8) {
+        let prev_lines = self.input_lines();
+        self.pressed |= button.mask();
+        self.check_interrupt(prev_lines, interrupt_flag);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    pub fn set_buttons(&mut self, buttons: &[Button], interrupt_flag: &mut u8) {
+        let prev_lines = self.input_lines();
+        self.pressed = buttons.iter().fold(0x00, |pressed, button| pressed | button.mask());
+        self.check_interrupt(prev_lines, interrupt_flag);
+    }
+
+    // Whether any button of the selected groups is held, which is what wakes the CPU from STOP
+    pub fn input_active(&self) -> bool {
+        self.input_lines() != 0x0F
+    }
+
+    // Low nibble of P1 (active low) for the currently selected button groups.
+    // P1 bit 4 low selects the direction keys and bit 5 low the action buttons
+    fn input_lines(&self) -> u8 {
+        let mut lines = 0x00;
+        if self.select & 0x10 == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            lines |= self.pressed >> 4;
+        }
+        !lines & 0x0F
+    }
+
+    // The interrupt is requested when any input line goes from high to low
+    fn check_interrupt(&self, prev_lines: u8, interrupt_flag: &mut u8) {
+        if prev_lines & !self.input_lines() != 0 {
+            *interrupt_flag |= InterruptMask::Joypad as u8;
+        }
+    }
+
+}
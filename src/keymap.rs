@@ -0,0 +1,136 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use sdl2::{controller, keyboard::Keycode};
+
+use beni_boy_color::Button;
+
+
+// Keyboard keys can also control the emulator itself. Every key has a single
+// action, so a key can't end up bound to both a button and rewinding
+#[derive(Clone, Copy)]
+pub enum KeyAction {
+    Button(Button),
+    Rewind
+}
+
+pub struct Keymap {
+    keys: HashMap<Keycode, KeyAction>,
+    controller_buttons: HashMap<controller::Button, Button>
+}
+
+#[derive(Debug)]
+pub enum KeymapError {
+    ReadError,
+    InvalidLine(usize)
+}
+
+impl fmt::Display for KeymapError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeymapError::ReadError => write!(f, "couldn't read the keymap file"),
+            KeymapError::InvalidLine(line) => write!(f, "invalid keymap entry on line {}", line)
+        }
+    }
+
+}
+
+impl std::error::Error for KeymapError {}
+
+impl Default for Keymap {
+
+    fn default() -> Keymap {
+        Keymap {
+            keys: HashMap::from([
+                (Keycode::Right, KeyAction::Button(Button::Right)),
+                (Keycode::Left, KeyAction::Button(Button::Left)),
+                (Keycode::Up, KeyAction::Button(Button::Up)),
+                (Keycode::Down, KeyAction::Button(Button::Down)),
+                (Keycode::X, KeyAction::Button(Button::A)),
+                (Keycode::Z, KeyAction::Button(Button::B)),
+                (Keycode::Backspace, KeyAction::Button(Button::Select)),
+                (Keycode::Return, KeyAction::Button(Button::Start)),
+                (Keycode::R, KeyAction::Rewind)
+            ]),
+            controller_buttons: HashMap::from([
+                (controller::Button::DPadRight, Button::Right),
+                (controller::Button::DPadLeft, Button::Left),
+                (controller::Button::DPadUp, Button::Up),
+                (controller::Button::DPadDown, Button::Down),
+                (controller::Button::A, Button::A),
+                (controller::Button::B, Button::B),
+                (controller::Button::Back, Button::Select),
+                (controller::Button::Start, Button::Start)
+            ])
+        }
+    }
+
+}
+
+impl Keymap {
+
+    // Loads a keymap on top of the default one. Each line binds a keyboard key
+    // or a controller button to a Game Boy button, e.g.:
+    //
+    //   key X = A
+    //   key Left Shift = Select
+    //   key Tab = Rewind
+    //   pad leftshoulder = B
+    //
+    // Key names are the SDL ones, empty lines and lines starting with '#' are ignored
+    pub fn from_file<P: AsRef<Path>>(path: &P) -> Result<Keymap, KeymapError> {
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Err(KeymapError::ReadError)
+        };
+
+        let mut keymap = Keymap::default();
+
+        for (line_num, line) in contents.lines().enumerate() {
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = KeymapError::InvalidLine(line_num + 1);
+
+            let Some((input, action)) = line.split_once('=') else {
+                return Err(invalid);
+            };
+            let action = match action.trim() {
+                action if action.eq_ignore_ascii_case("rewind") => KeyAction::Rewind,
+                action => match Button::from_name(action) {
+                    Some(button) => KeyAction::Button(button),
+                    None => return Err(invalid)
+                }
+            };
+
+            // Binding a key replaces whatever it was bound to before,
+            // rewinding can only be bound to keyboard keys
+            match (input.trim().split_once(' '), action) {
+                (Some(("key", name)), action) => match Keycode::from_name(name.trim()) {
+                    Some(keycode) => { keymap.keys.insert(keycode, action); },
+                    None => return Err(invalid)
+                },
+                (Some(("pad", name)), KeyAction::Button(button)) => match controller::Button::from_string(name.trim()) {
+                    Some(pad_button) => { keymap.controller_buttons.insert(pad_button, button); },
+                    None => return Err(invalid)
+                },
+                _ => return Err(invalid)
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    pub fn key(&self, keycode: Keycode) -> Option<KeyAction> {
+        self.keys.get(&keycode).copied()
+    }
+
+    pub fn controller_button(&self, button: controller::Button) -> Option<Button> {
+        self.controller_buttons.get(&button).copied()
+    }
+
+}
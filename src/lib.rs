@@ -0,0 +1,22 @@
+mod apu;
+mod cpu;
+mod mmu;
+mod cartridge;
+mod ppu;
+mod timer;
+mod joypad;
+mod beni_boy_color;
+mod error;
+mod recorder;
+mod savestate;
+mod rewind;
+
+pub use self::apu::{Resampler, AUDIO_SAMPLE_RATE};
+pub use self::beni_boy_color::{BeniBoyColor, FRAME_RATE, M_CYCLES_PER_FRAME};
+pub use self::cartridge::{sibling_path, CartridgeError, CartridgeHeader, CgbSupport, InfraredPort, Licensee};
+pub use self::cpu::CpuStatus;
+pub use self::error::EmulatorError;
+pub use self::joypad::Button;
+pub use self::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use self::recorder::{RecordingOptions, VideoRecording};
+pub use self::savestate::{SaveStateError, SAVE_STATE_VERSION};
@@ -1,35 +1,101 @@
 extern crate sdl2;
 
+use beni_boy_color::{sibling_path, BeniBoyColor, CpuStatus, RecordingOptions, VideoRecording, FRAME_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
 use bytemuck::cast_slice;
-use sdl2::{event::{Event, WindowEvent}, pixels::PixelFormatEnum};
-use std::{env, time::{Duration, Instant}};
-
-mod cpu;
-mod mmu;
-mod cartridge;
-mod ppu;
-mod timer;
-mod beni_boy_color;
-
-const FRAME_RATE: f64 = 59.7275;
-const M_CYCLES_PER_FRAME: u32 = 17556;
+use sdl2::{event::{Event, WindowEvent}, keyboard::{Keycode, Mod}, pixels::PixelFormatEnum};
+use std::{env, path::{Path, PathBuf}, process, time::{Duration, Instant}};
+
+mod audio;
+mod keymap;
+
+use keymap::KeyAction;
+
 const SCREEN_SIZE_MULTIPLIER: u32 = 6;
+const RUMBLE_DURATION_MS: u32 = 100;
+const REWIND_SECONDS: f64 = 10.0;
 
 fn main() {
 
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        panic!()
+        eprintln!(
+            "Usage: {} <rom> [--keymap <file>] [--record-audio <file.wav>] [--record-video <dir>] [--record-raw <file.rgba>]\n\
+             F1-F10 load a save state slot, Shift+F1-F10 save it, hold R to rewind",
+            args[0]
+        );
+        process::exit(1);
     }
 
     let rom_path = &args[1];
-    let mut gbc = beni_boy_color::BeniBoyColor::new(rom_path);
+    let mut gbc = match BeniBoyColor::new(rom_path) {
+        Ok(gbc) => gbc,
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", rom_path, err);
+            process::exit(1);
+        }
+    };
+
+    let header = gbc.cartridge_header();
+    println!("{}", header);
+    if !header.global_checksum_valid() {
+        eprintln!(
+            "Warning: global checksum mismatch for \"{}\" (expected {:04X}, computed {:04X})",
+            header.title, header.global_checksum, header.computed_global_checksum()
+        );
+    }
+
+    let keymap = match arg_value(&args, "--keymap") {
+        Some(path) => {
+            match keymap::Keymap::from_file(path) {
+                Ok(keymap) => keymap,
+                Err(err) => {
+                    eprintln!("Failed to load keymap {}: {}", path, err);
+                    process::exit(1);
+                }
+            }
+        },
+        None => keymap::Keymap::default()
+    };
+
+    let recording = RecordingOptions {
+        audio: arg_value(&args, "--record-audio").map(PathBuf::from),
+        video: match (arg_value(&args, "--record-video"), arg_value(&args, "--record-raw")) {
+            (Some(dir), _) => Some(VideoRecording::PngSequence(PathBuf::from(dir))),
+            (None, Some(path)) => Some(VideoRecording::RawRgba(PathBuf::from(path))),
+            (None, None) => None
+        }
+    };
+    if recording.audio.is_some() || recording.video.is_some() {
+        if let Err(err) = gbc.start_recording(&recording) {
+            eprintln!("Failed to start recording: {}", err);
+            process::exit(1);
+        }
+    }
 
     // Setup SDL
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    let audio_subsystem = sdl_context.audio().unwrap();
+
+    // Audio drives the frame pacing, if it can't be opened we fall back to sleeping
+    let mut audio_output = match audio::AudioOutput::new(&audio_subsystem) {
+        Ok(audio_output) => Some(audio_output),
+        Err(err) => {
+            eprintln!("Failed to open audio device, running without sound: {}", err);
+            None
+        }
+    };
+
+    // Opened controllers have to be kept alive to keep receiving their events
+    let mut controllers = Vec::new();
+    let mut rumbling = false;
+    let mut lock_reported = false;
 
-    let window = video_subsystem.window("BeniBoy Color", ppu::SCREEN_WIDTH as u32 * SCREEN_SIZE_MULTIPLIER, ppu::SCREEN_HEIGHT as u32 * SCREEN_SIZE_MULTIPLIER)
+    gbc.set_rewind_frames((REWIND_SECONDS * FRAME_RATE) as usize);
+    let mut rewinding = false;
+
+    let window = video_subsystem.window("BeniBoy Color", SCREEN_WIDTH as u32 * SCREEN_SIZE_MULTIPLIER, SCREEN_HEIGHT as u32 * SCREEN_SIZE_MULTIPLIER)
         .position_centered()
         .build()
         .unwrap();
@@ -40,7 +106,7 @@ fn main() {
     
     let texture_creator = canvas.texture_creator();
     
-    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::ARGB8888, ppu::SCREEN_WIDTH as u32, ppu::SCREEN_HEIGHT as u32).unwrap();
+    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::ARGB8888, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32).unwrap();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
@@ -55,25 +121,168 @@ fn main() {
                 Event::Window { win_event: WindowEvent::Close, .. } => {
                     break 'main_loop
                 },
+
+                // F1-F10 load the save state slots, holding shift saves them instead
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => {
+                    if let Some(slot) = state_slot(keycode) {
+                        let path = sibling_path(Path::new(rom_path), &format!("ss{}", slot));
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            save_state(&gbc, &path, slot);
+                        } else {
+                            load_state(&mut gbc, &path, slot);
+                        }
+                    } else {
+                        match keymap.key(keycode) {
+                            Some(KeyAction::Button(button)) => gbc.press(button),
+                            Some(KeyAction::Rewind) => rewinding = true,
+                            None => {}
+                        }
+                    }
+                },
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    match keymap.key(keycode) {
+                        Some(KeyAction::Button(button)) => gbc.release(button),
+                        Some(KeyAction::Rewind) => rewinding = false,
+                        None => {}
+                    }
+                },
+
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(controller) = controller_subsystem.open(which) {
+                        controllers.push(controller);
+                    }
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers.retain(|controller| controller.instance_id() != which);
+                },
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(button) = keymap.controller_button(button) {
+                        gbc.press(button);
+                    }
+                },
+                Event::ControllerButtonUp { button, .. } => {
+                    if let Some(button) = keymap.controller_button(button) {
+                        gbc.release(button);
+                    }
+                },
+
                 _ => {}
             }
         }
 
-        for _ in 0..M_CYCLES_PER_FRAME {
-            gbc.tick();
+        // While rewinding frames are stepped back at the normal frame rate and no audio is played
+        if rewinding {
+            if let Err(err) = gbc.rewind_frame() {
+                eprintln!("Failed to rewind: {}", err);
+                rewinding = false;
+            }
+        } else {
+            gbc.run_frame();
+            if let Some(err) = gbc.take_recording_error() {
+                eprintln!("Recording stopped: {}", err);
+            }
+
+            let samples = gbc.audio_samples();
+            if let Some(audio_output) = audio_output.as_mut() {
+                audio_output.queue_samples(&samples);
+            }
+        }
+
+        if let CpuStatus::Locked { opcode, addr } = gbc.cpu_status() {
+            if !lock_reported {
+                eprintln!("CPU locked up after executing illegal opcode {:02X} at {:04X}", opcode, addr);
+                lock_reported = true;
+            }
+        }
+
+        // Rumble carts toggle the motor very fast to control its strength, so the
+        // state is sampled once per frame and refreshed while it stays on
+        let rumble = gbc.rumble();
+        if rumble || rumbling {
+            let strength = if rumble { 0xFFFF } else { 0x0000 };
+            for controller in controllers.iter_mut() {
+                let _ = controller.set_rumble(strength, strength, RUMBLE_DURATION_MS);
+            }
+        }
+        rumbling = rumble;
+
+        if let Err(err) = gbc.autosave() {
+            eprintln!("Failed to write save file: {}", err);
         }
 
         //canvas.clear();
-        let _ = texture.update(None, cast_slice(gbc.mmu.ppu.screen.as_ref()), ppu::SCREEN_WIDTH * 4);
+        let _ = texture.update(None, cast_slice(gbc.framebuffer().as_ref()), SCREEN_WIDTH * 4);
         canvas.copy(&texture, None, None).unwrap();
         canvas.present();
 
-        let frame_duration = Duration::from_secs_f64(1.0 / FRAME_RATE);
-        std::thread::sleep(
-            frame_duration
-                .checked_sub(start.elapsed())
-                .unwrap_or(Duration::ZERO)
-        );
+        match audio_output.as_ref() {
+            Some(audio_output) if !rewinding => audio_output.wait(),
+            _ => {
+                let frame_duration = Duration::from_secs_f64(1.0 / FRAME_RATE);
+                std::thread::sleep(
+                    frame_duration
+                        .checked_sub(start.elapsed())
+                        .unwrap_or(Duration::ZERO)
+                );
+            }
+        }
+    }
+
+    if let Err(err) = gbc.save_ram() {
+        eprintln!("Failed to write save file: {}", err);
+    }
+
+    if let Err(err) = gbc.stop_recording() {
+        eprintln!("Failed to finish recording: {}", err);
+    }
+
+}
+
+fn save_state(gbc: &BeniBoyColor, path: &Path, slot: u8) {
+    match std::fs::write(path, gbc.save_state()) {
+        Ok(()) => println!("Saved state to slot {}", slot),
+        Err(err) => eprintln!("Failed to write save state {}: {}", path.display(), err)
     }
+}
 
+fn load_state(gbc: &mut BeniBoyColor, path: &Path, slot: u8) {
+    let state = match std::fs::read(path) {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("Failed to read save state {}: {}", path.display(), err);
+            return;
+        }
+    };
+    match gbc.load_state(&state) {
+        Ok(()) => println!("Loaded state from slot {}", slot),
+        Err(err) => eprintln!("Failed to load state from slot {}: {}", slot, err)
+    }
+}
+
+fn state_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        Keycode::F10 => Some(10),
+        _ => None
+    }
+}
+
+// Value following a command line flag, e.g. the path in `--keymap <path>`
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a String> {
+    let idx = args.iter().position(|arg| arg == flag)?;
+    match args.get(idx + 1) {
+        Some(value) => Some(value),
+        None => {
+            eprintln!("{} requires a value", flag);
+            process::exit(1);
+        }
+    }
 }
@@ -1,38 +1,48 @@
-use crate::{cartridge::Cartridge, ppu::Ppu, timer::Timer};
+use crate::{apu::Apu, cartridge::{Cartridge, CartridgeHeader, CgbSupport, InfraredPort}, joypad::Joypad, ppu::Ppu, timer::Timer};
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
 pub struct Mmu {
     cart: Cartridge,
     pub ppu: Ppu,
+    pub apu: Apu,
     pub timer: Timer,
+    pub joypad: Joypad,
 
     wram: Box<[u8; 0x2000]>,
     hram: Box<[u8; 0x007F]>,
     io_regs: Box<[u8; 0x0080]>,  // Temporary
     pub interrupt_enable: u8,
-    pub interrupt_flag: u8
+    pub interrupt_flag: u8,
+
+    cgb_mode: bool,
+    // KEY1: armed speed switch and current speed. In double speed the PPU and
+    // APU only advance every other CPU M-cycle
+    speed_switch_armed: bool,
+    double_speed: bool,
+    odd_cycle: bool
 }
 
 impl Mmu {
 
-    pub fn new(rom_path: &str) -> Mmu {
+    pub fn new(cartridge: Cartridge) -> Mmu {
 
-        let cartridge = Cartridge::new(&rom_path);
-        let cartridge = match cartridge {
-            Ok(cart) => cart,
-            Err(err) => {
-                panic!();  // We'll deal with the error later...
-            }
-        };
+        let cgb_mode = cartridge.header().cgb_support != CgbSupport::DmgOnly;
 
         Mmu {
             cart: cartridge,
             ppu: Ppu::new(),
+            apu: Apu::new(),
             timer: Timer::new(),
+            joypad: Joypad::new(),
             wram: vec![0; 0x2000].into_boxed_slice().try_into().expect("Array size mismatch!"),
             hram: vec![0; 0x007F].into_boxed_slice().try_into().expect("Array size mismatch!"),
             io_regs: vec![0; 0x0080].into_boxed_slice().try_into().expect("Array size mismatch!"),
             interrupt_enable: 0x00,
-            interrupt_flag: 0xE1
+            interrupt_flag: 0xE1,
+            cgb_mode,
+            speed_switch_armed: false,
+            double_speed: false,
+            odd_cycle: false
         }
     }
 
@@ -40,6 +50,83 @@ impl Mmu {
 
 impl Mmu {
 
+    pub fn cartridge_header(&self) -> &CartridgeHeader {
+        self.cart.header()
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.cart.rumble()
+    }
+
+    pub fn save_ram(&mut self) -> std::io::Result<()> {
+        self.cart.save_ram()
+    }
+
+    pub fn autosave(&mut self) -> std::io::Result<()> {
+        self.cart.autosave()
+    }
+
+    pub fn set_infrared_port(&mut self, port: Box<dyn InfraredPort>) {
+        self.cart.set_infrared_port(port);
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // STOP switches speed instead of stopping the system when KEY1 bit 0 is set
+    pub fn speed_switch_armed(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    pub fn switch_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.odd_cycle = false;
+        self.timer.reset_div();
+    }
+
+    pub fn rom_crc(&self) -> u32 {
+        self.cart.rom_crc()
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(self.wram.as_ref());
+        state.write_bytes(self.hram.as_ref());
+        state.write_bytes(self.io_regs.as_ref());
+        state.write_u8(self.interrupt_enable);
+        state.write_u8(self.interrupt_flag);
+        state.write_bool(self.speed_switch_armed);
+        state.write_bool(self.double_speed);
+        state.write_bool(self.odd_cycle);
+
+        self.cart.save_state(state);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.timer.save_state(state);
+        self.joypad.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.read_into(self.wram.as_mut())?;
+        state.read_into(self.hram.as_mut())?;
+        state.read_into(self.io_regs.as_mut())?;
+        self.interrupt_enable = state.read_u8()?;
+        self.interrupt_flag = state.read_u8()?;
+        self.speed_switch_armed = state.read_bool()?;
+        self.double_speed = state.read_bool()?;
+        self.odd_cycle = state.read_bool()?;
+        if !self.cgb_mode && (self.speed_switch_armed || self.double_speed) {
+            return Err(SaveStateError::InvalidDataError);
+        }
+
+        self.cart.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.joypad.load_state(state)
+    }
+
     pub fn read_byte(&self, addr: u16) -> u8 {
 
         match addr {
@@ -71,6 +158,9 @@ impl Mmu {
             0xFF00 ..= 0xFF7F => {
                 match ((addr - 0xFF00) & 0x7F) as u8 {
 
+                    // P1
+                    0x00 => self.joypad.read(),
+
                     // DIV
                     0x04 => (self.timer.div >> 8) as u8,
 
@@ -86,8 +176,15 @@ impl Mmu {
                     // IF
                     0x0F => self.interrupt_flag,
 
-                    // LY
-                    0x44 => 0x90,
+                    // Sound regs and wave RAM
+                    0x10 ..= 0x3F => self.apu.read_register(addr),
+
+                    // LCD regs
+                    0x40 ..= 0x45 | 0x47 ..= 0x4B => self.ppu.read_register(addr),
+
+                    // KEY1
+                    0x4D if self.cgb_mode => 0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8,
+                    0x4D => 0xFF,
 
                     _ => self.io_regs[(addr - 0xFF00) as usize]
                 }
@@ -101,10 +198,6 @@ impl Mmu {
         }
     }
 
-    pub fn read_word(&self, addr: u16) -> u16 {
-        (self.read_byte(addr + 1) as u16) << 8 | self.read_byte(addr) as u16
-    }
-
     pub fn write_byte(&mut self, addr: u16, data: u8) {
         
         match addr {
@@ -135,34 +228,32 @@ impl Mmu {
             // IO Regs
             0xFF00 ..= 0xFF7F => {
                 self.io_regs[(addr - 0xFF00) as usize] = data;
-                if addr == 0xFF && data == 0x81 {
-                    print!("{}", self.io_regs[1] as char);
-                }
                 match ((addr - 0xFF00) & 0x7F) as u8 {
 
-                    //
-                    0x02 => {
-                        if data == 0x81 {
-                            print!("{}", self.io_regs[1] as char)
-                        }
-                        self.io_regs[(addr - 0xFF00) as usize] = data
-                    },
+                    // P1
+                    0x00 => self.joypad.write(data, &mut self.interrupt_flag),
 
                     // DIV
-                    0x04 => self.timer.div = 0,
+                    0x04 => self.timer.reset_div(),
 
                     // TIMA
-                    0x05 => self.timer.tima = data,
+                    0x05 => self.timer.write_tima(data),
 
                     // TMA
-                    0x06 => self.timer.tma = data,
+                    0x06 => self.timer.write_tma(data),
 
                     // TAC
-                    0x07 => self.timer.tac = 0xF8 | (data & 0x07),
+                    0x07 => self.timer.write_tac(data),
 
                     // IF
                     0x0F => self.interrupt_flag = data & 0x1F,
 
+                    // Sound regs and wave RAM
+                    0x10 ..= 0x3F => self.apu.write_register(addr, data),
+
+                    // LCD regs
+                    0x40 ..= 0x45 | 0x47 ..= 0x4B => self.ppu.write_register(addr, data),
+
                     // OAM DMA
                     0x46 => {
                         // For now we just copy all the data at once
@@ -176,6 +267,9 @@ impl Mmu {
                         }
                     },
 
+                    // KEY1, only the speed switch arm bit is writable
+                    0x4D => self.speed_switch_armed = self.cgb_mode && data & 0x01 != 0,
+
                     _ => self.io_regs[(addr - 0xFF00) as usize] = data
                 }
             }
@@ -189,13 +283,33 @@ impl Mmu {
 
     }
 
-    pub fn write_word(&mut self, addr: u16, data: u16) {
-        self.write_byte(addr, (data & 0x00FF) as u8);
-        self.write_byte( addr+1, ((data & 0xFF00) >> 8) as u8);
+    // The timer runs off the CPU clock, so it is twice as fast in double speed mode
+    pub fn tick_components(&mut self, m_cycles: u64) {
+        for _ in 0..m_cycles {
+            self.timer.tick(1, &mut self.interrupt_flag);
+            if self.normal_speed_cycle() {
+                self.ppu.tick(1, &mut self.interrupt_flag);
+                self.apu.tick(1, self.timer.div >> self.double_speed as u8);
+            }
+        }
     }
 
-    pub fn tick_components(&mut self, m_cycles: u64) {
-        self.timer.tick(m_cycles, &mut self.interrupt_flag);
+    // The system clock is stopped during STOP, only the audio output keeps going
+    pub fn tick_stopped(&mut self, m_cycles: u64) {
+        for _ in 0..m_cycles {
+            if self.normal_speed_cycle() {
+                self.apu.output_silence(1);
+            }
+        }
+    }
+
+    // Whether this CPU M-cycle also ends an M-cycle of the normal speed clock
+    fn normal_speed_cycle(&mut self) -> bool {
+        if !self.double_speed {
+            return true;
+        }
+        self.odd_cycle = !self.odd_cycle;
+        !self.odd_cycle
     }
 
 }
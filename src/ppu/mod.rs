@@ -1,9 +1,25 @@
+mod render;
+mod sprites;
+
+use crate::cpu::InterruptMask;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+use self::sprites::Sprite;
+
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
 
-enum PpuState {
-    Glitched,
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const PIXEL_TRANSFER_DOTS: u16 = 172;
+const LINES_PER_FRAME: u8 = 154;
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum PpuState {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    PixelTransfer = 3
 }
 
 const GB_PALETTE: [u32; 4] = [
@@ -20,6 +36,17 @@ pub struct Ppu {
     vram: Box<[u8; 0x2000]>,
     oam: Box<[u8; 0x00A0]>,
 
+    line_sprites: Vec<Sprite>,
+    bg_line: [u8; SCREEN_WIDTH],
+
+    window_line: u8,
+    window_y_triggered: bool,
+
+    state: PpuState,
+    line_dots: u16,
+    stat_line: bool,
+    frame_ready: bool,
+
     wx: u8,
     wy: u8,
     ly: u8,
@@ -42,6 +69,14 @@ impl Ppu {
             screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice().try_into().expect("Array size mismatch!"),
             vram: vec![0; 0x2000].into_boxed_slice().try_into().expect("Array size mismatch!"),
             oam: vec![0; 0x00A0].into_boxed_slice().try_into().expect("Array size mismatch!"),
+            line_sprites: Vec::with_capacity(sprites::MAX_SPRITES_PER_LINE),
+            bg_line: [0; SCREEN_WIDTH],
+            window_line: 0,
+            window_y_triggered: false,
+            state: PpuState::OamScan,
+            line_dots: 0,
+            stat_line: false,
+            frame_ready: false,
             wx: 0x00,
             wy: 0x00,
             ly: 0x00,
@@ -52,7 +87,7 @@ impl Ppu {
             obp0: 0xFF, // Revise
             obp1: 0xFF, // Revise
             lcdc: 0x91,
-            stat: 0x85
+            stat: 0x80
         }
     }
 
@@ -72,14 +107,239 @@ impl Ppu {
         self.oam[addr as usize] = data;
     }
 
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    // The LCD shows a blank (white) screen while the system is stopped
+    pub fn blank_screen(&mut self) {
+        self.screen.fill(GB_PALETTE[0]);
+    }
+
+    // Returns whether a new frame has been completed since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = ((self.ly == self.lyc) as u8) << 2;
+                self.stat | coincidence | self.state as u8
+            },
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled = self.lcdc & 0x80 != 0;
+                let enabled = data & 0x80 != 0;
+                self.lcdc = data;
+
+                // Turning the LCD off resets LY and leaves the PPU in mode 0,
+                // turning it back on starts a fresh frame
+                if was_enabled && !enabled {
+                    self.ly = 0;
+                    self.line_dots = 0;
+                    self.state = PpuState::HBlank;
+                    self.stat_line = false;
+                } else if !was_enabled && enabled {
+                    self.line_dots = 0;
+                    self.state = PpuState::OamScan;
+                    self.window_line = 0;
+                    self.window_y_triggered = false;
+                }
+            },
+            // Mode and coincidence bits are read only
+            0xFF41 => self.stat = 0x80 | (data & 0x78),
+            0xFF42 => self.scy = data,
+            0xFF43 => self.scx = data,
+            // LY is read only, writing to it resets the line counter
+            0xFF44 => {
+                self.ly = 0;
+                self.line_dots = 0;
+                if self.lcdc & 0x80 != 0 {
+                    self.state = PpuState::OamScan;
+                }
+            },
+            0xFF45 => self.lyc = data,
+            0xFF47 => self.bgp = data,
+            0xFF48 => self.obp0 = data,
+            0xFF49 => self.obp1 = data,
+            0xFF4A => self.wy = data,
+            0xFF4B => self.wx = data,
+            _ => {}
+        }
+    }
+
+    // The screen is included so a loaded state shows its frame right away
+    pub fn save_state(&self, state: &mut StateWriter) {
+
+        for pixel in self.screen.iter() {
+            state.write_u32(*pixel);
+        }
+        state.write_bytes(self.vram.as_ref());
+        state.write_bytes(self.oam.as_ref());
+
+        state.write_u8(self.window_line);
+        state.write_bool(self.window_y_triggered);
+
+        state.write_u8(self.state as u8);
+        state.write_u16(self.line_dots);
+        state.write_bool(self.stat_line);
+        state.write_bool(self.frame_ready);
+
+        state.write_bytes(&[
+            self.wx, self.wy, self.ly, self.lyc, self.scx, self.scy,
+            self.bgp, self.obp0, self.obp1, self.lcdc, self.stat
+        ]);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+
+        for pixel in self.screen.iter_mut() {
+            *pixel = state.read_u32()?;
+        }
+        state.read_into(self.vram.as_mut())?;
+        state.read_into(self.oam.as_mut())?;
+
+        self.window_line = state.read_u8()?;
+        self.window_y_triggered = state.read_bool()?;
+
+        self.state = match state.read_u8()? {
+            0 => PpuState::HBlank,
+            1 => PpuState::VBlank,
+            2 => PpuState::OamScan,
+            3 => PpuState::PixelTransfer,
+            _ => return Err(SaveStateError::InvalidDataError)
+        };
+        self.line_dots = state.read_u16()?;
+        self.stat_line = state.read_bool()?;
+        self.frame_ready = state.read_bool()?;
+
+        let mut regs = [0; 11];
+        state.read_into(&mut regs)?;
+        let [wx, wy, ly, lyc, scx, scy, bgp, obp0, obp1, lcdc, stat] = regs;
+        // Only lines inside the screen can be rendered
+        let in_vblank = self.state == PpuState::VBlank;
+        if ly >= LINES_PER_FRAME || in_vblank != (ly as usize >= SCREEN_HEIGHT) || self.line_dots >= DOTS_PER_LINE {
+            return Err(SaveStateError::InvalidDataError);
+        }
+        self.wx = wx;
+        self.wy = wy;
+        self.ly = ly;
+        self.lyc = lyc;
+        self.scx = scx;
+        self.scy = scy;
+        self.bgp = bgp;
+        self.obp0 = obp0;
+        self.obp1 = obp1;
+        self.lcdc = lcdc;
+        self.stat = 0x80 | (stat & 0x78);
+
+        // The sprites of the current line are only needed until it is rendered
+        self.line_sprites.clear();
+        if self.state == PpuState::PixelTransfer {
+            self.scan_oam();
+        }
+
+        Ok(())
+    }
+
     pub fn tick(&mut self, m_cycles: u64, interrupt_flag: &mut u8) {
 
         for _ in 0..m_cycles {
 
+            // The PPU is completely stopped while the LCD is off
+            if self.lcdc & 0x80 == 0 {
+                continue;
+            }
+
+            self.line_dots += 4;
+
+            match self.state {
+
+                PpuState::OamScan => {
+                    if self.line_dots >= OAM_SCAN_DOTS {
+                        // Once LY has matched WY the window stays enabled for the rest of the frame
+                        if self.ly == self.wy {
+                            self.window_y_triggered = true;
+                        }
+                        self.scan_oam();
+                        self.state = PpuState::PixelTransfer;
+                    }
+                },
+
+                PpuState::PixelTransfer => {
+                    // For now the whole line is drawn at once at the end of mode 3
+                    if self.line_dots >= OAM_SCAN_DOTS + PIXEL_TRANSFER_DOTS {
+                        self.render_scanline();
+                        self.state = PpuState::HBlank;
+                    }
+                },
+
+                PpuState::HBlank => {
+                    if self.line_dots >= DOTS_PER_LINE {
+                        self.line_dots = 0;
+                        self.ly += 1;
+                        if self.ly as usize == SCREEN_HEIGHT {
+                            self.state = PpuState::VBlank;
+                            self.frame_ready = true;
+                            *interrupt_flag |= InterruptMask::VBlank as u8;
+                        } else {
+                            self.state = PpuState::OamScan;
+                        }
+                    }
+                },
 
+                PpuState::VBlank => {
+                    if self.line_dots >= DOTS_PER_LINE {
+                        self.line_dots = 0;
+                        self.ly += 1;
+                        if self.ly == LINES_PER_FRAME {
+                            self.ly = 0;
+                            self.state = PpuState::OamScan;
+                            self.window_line = 0;
+                            self.window_y_triggered = false;
+                        }
+                    }
+                }
 
+            }
+
+            self.update_stat_line(interrupt_flag);
         }
 
     }
 
+    // All STAT sources are ORed into a single line and the interrupt is only
+    // requested on its rising edge, so a source going high while another one
+    // is already active doesn't trigger a new interrupt ("STAT blocking")
+    fn update_stat_line(&mut self, interrupt_flag: &mut u8) {
+
+        let stat_line = match self.state {
+            PpuState::HBlank => self.stat & 0x08 != 0,
+            PpuState::VBlank => self.stat & 0x10 != 0,
+            PpuState::OamScan => self.stat & 0x20 != 0,
+            PpuState::PixelTransfer => false
+        } || (self.stat & 0x40 != 0 && self.ly == self.lyc);
+
+        if stat_line && !self.stat_line {
+            *interrupt_flag |= InterruptMask::Stat as u8;
+        }
+        self.stat_line = stat_line;
+    }
+
 }
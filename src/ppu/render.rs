@@ -0,0 +1,78 @@
+use super::{Ppu, GB_PALETTE, SCREEN_WIDTH};
+
+
+impl Ppu {
+
+    pub(super) fn render_scanline(&mut self) {
+        self.render_background();
+        self.render_window();
+        self.render_sprites();
+    }
+
+    fn render_background(&mut self) {
+
+        let line_start = self.ly as usize * SCREEN_WIDTH;
+
+        // On DMG, clearing LCDC bit 0 makes the background blank (white)
+        if self.lcdc & 0x01 == 0 {
+            self.screen[line_start .. line_start + SCREEN_WIDTH].fill(GB_PALETTE[0]);
+            self.bg_line.fill(0);
+            return;
+        }
+
+        let map_base: usize = if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+        let y = self.ly.wrapping_add(self.scy);
+
+        for x in 0..SCREEN_WIDTH {
+            let px = (x as u8).wrapping_add(self.scx);
+            let tile_idx = self.vram[map_base + (y as usize / 8) * 32 + px as usize / 8];
+            let color = self.tile_pixel(self.bg_tile_addr(tile_idx), px % 8, y % 8);
+            self.bg_line[x] = color;
+            self.screen[line_start + x] = GB_PALETTE[((self.bgp >> (color * 2)) & 0x03) as usize];
+        }
+
+    }
+
+    fn render_window(&mut self) {
+
+        // On DMG LCDC bit 0 disables the window as well as the background
+        if self.lcdc & 0x21 != 0x21 || !self.window_y_triggered || self.wx > 166 {
+            return;
+        }
+
+        let line_start = self.ly as usize * SCREEN_WIDTH;
+        let map_base: usize = if self.lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+        let y = self.window_line;
+
+        // WX holds the window's X position plus 7
+        let start_x = (self.wx as usize).saturating_sub(7);
+
+        for x in start_x..SCREEN_WIDTH {
+            let px = (x + 7 - self.wx as usize) as u8;
+            let tile_idx = self.vram[map_base + (y as usize / 8) * 32 + px as usize / 8];
+            let color = self.tile_pixel(self.bg_tile_addr(tile_idx), px % 8, y % 8);
+            self.bg_line[x] = color;
+            self.screen[line_start + x] = GB_PALETTE[((self.bgp >> (color * 2)) & 0x03) as usize];
+        }
+
+        // The internal line counter only advances on lines where the window was drawn
+        self.window_line += 1;
+    }
+
+    // LCDC bit 4 selects between the unsigned (0x8000) and signed (0x8800) addressing modes
+    fn bg_tile_addr(&self, tile_idx: u8) -> usize {
+        if self.lcdc & 0x10 != 0 {
+            tile_idx as usize * 16
+        } else {
+            (0x1000 + tile_idx as i8 as isize * 16) as usize
+        }
+    }
+
+    pub(super) fn tile_pixel(&self, tile_addr: usize, x: u8, y: u8) -> u8 {
+        let lo = self.vram[tile_addr + y as usize * 2];
+        let hi = self.vram[tile_addr + y as usize * 2 + 1];
+        let bit = 7 - x;
+        (((hi >> bit) & 0x01) << 1) | ((lo >> bit) & 0x01)
+    }
+
+}
@@ -0,0 +1,97 @@
+use super::{Ppu, GB_PALETTE, SCREEN_WIDTH};
+
+
+pub const MAX_SPRITES_PER_LINE: usize = 10;
+
+#[derive(Clone, Copy)]
+pub struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    flags: u8,
+    // Height when the sprite was selected, LCDC can change before the line is drawn
+    height: u8
+}
+
+impl Ppu {
+
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    // Selects the first 10 sprites (in OAM order) that overlap the current line
+    pub(super) fn scan_oam(&mut self) {
+
+        self.line_sprites.clear();
+
+        let height = self.sprite_height();
+        let line = self.ly as u16 + 16;
+
+        for entry in self.oam.chunks_exact(4) {
+            let y = entry[0] as u16;
+            if line >= y && line < y + height as u16 {
+                self.line_sprites.push(Sprite { y: entry[0], x: entry[1], tile: entry[2], flags: entry[3], height });
+                if self.line_sprites.len() == MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+
+        // On DMG the sprite with the smallest X wins, ties are broken by OAM order.
+        // The sort is stable so OAM order is kept for equal X coordinates
+        self.line_sprites.sort_by_key(|sprite| sprite.x);
+    }
+
+    pub(super) fn render_sprites(&mut self) {
+
+        if self.lcdc & 0x02 == 0 {
+            return;
+        }
+
+        let line_start = self.ly as usize * SCREEN_WIDTH;
+
+        // Pixels already claimed by a higher priority sprite. A sprite pixel hidden
+        // behind the background still claims it, so lower priority sprites can't show
+        let mut claimed = [false; SCREEN_WIDTH];
+
+        for sprite in self.line_sprites.iter() {
+
+            let mut row = self.ly + 16 - sprite.y;
+            if sprite.flags & 0x40 != 0 {
+                row = sprite.height - 1 - row;
+            }
+
+            let tile = if sprite.height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+            let tile_addr = tile as usize * 16 + (row as usize / 8) * 16;
+
+            let palette = if sprite.flags & 0x10 != 0 { self.obp1 } else { self.obp0 };
+            let bg_over_obj = sprite.flags & 0x80 != 0;
+
+            for col in 0..8u8 {
+
+                let x = sprite.x as isize - 8 + col as isize;
+                if !(0 .. SCREEN_WIDTH as isize).contains(&x) {
+                    continue;
+                }
+                let x = x as usize;
+
+                let tile_col = if sprite.flags & 0x20 != 0 { 7 - col } else { col };
+                let color = self.tile_pixel(tile_addr, tile_col, row % 8);
+
+                // Color 0 is transparent for sprites
+                if color == 0 || claimed[x] {
+                    continue;
+                }
+                claimed[x] = true;
+
+                if bg_over_obj && self.bg_line[x] != 0 {
+                    continue;
+                }
+
+                self.screen[line_start + x] = GB_PALETTE[((palette >> (color * 2)) & 0x03) as usize];
+            }
+        }
+
+    }
+
+}
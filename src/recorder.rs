@@ -0,0 +1,170 @@
+use std::{fs::File, io::{self, BufWriter, Seek, SeekFrom, Write}, path::PathBuf};
+
+use crate::apu::{Resampler, AUDIO_SAMPLE_RATE};
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+
+const WAV_SAMPLE_RATE: u32 = 48000;
+const WAV_HEADER_SIZE: u32 = 44;
+
+pub enum VideoRecording {
+    // Numbered PNG files (frame_000000.png, ...) inside the given directory
+    PngSequence(PathBuf),
+    // Raw RGBA frames appended to a single file, ffmpeg can read it with
+    // -f rawvideo -pixel_format rgba -video_size 160x144 -framerate 59.7275
+    RawRgba(PathBuf)
+}
+
+#[derive(Default)]
+pub struct RecordingOptions {
+    pub audio: Option<PathBuf>,
+    pub video: Option<VideoRecording>
+}
+
+// 16 bit stereo PCM WAV file. The sizes in the header are filled in when finishing
+struct WavWriter {
+    file: BufWriter<File>,
+    resampler: Resampler,
+    buffer: Vec<f32>,
+    data_size: u32
+}
+
+impl WavWriter {
+
+    fn new(path: &PathBuf) -> io::Result<WavWriter> {
+        let mut wav = WavWriter {
+            file: BufWriter::new(File::create(path)?),
+            resampler: Resampler::new(AUDIO_SAMPLE_RATE, WAV_SAMPLE_RATE),
+            buffer: Vec::new(),
+            data_size: 0
+        };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let channels: u16 = 2;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.write_all(b"WAVE")?;
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&1u16.to_le_bytes())?;  // PCM
+        self.file.write_all(&channels.to_le_bytes())?;
+        self.file.write_all(&WAV_SAMPLE_RATE.to_le_bytes())?;
+        self.file.write_all(&(WAV_SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        self.file.write_all(&block_align.to_le_bytes())?;
+        self.file.write_all(&bits_per_sample.to_le_bytes())?;
+        self.file.write_all(b"data")?;
+        self.file.write_all(&self.data_size.to_le_bytes())
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.buffer.clear();
+        self.resampler.process(samples, &mut self.buffer);
+        for sample in self.buffer.iter() {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += self.buffer.len() as u32 * 2;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+
+}
+
+enum VideoWriter {
+    PngSequence { dir: PathBuf, frame: u32 },
+    RawRgba(BufWriter<File>)
+}
+
+impl VideoWriter {
+
+    fn new(recording: &VideoRecording) -> io::Result<VideoWriter> {
+        match recording {
+            VideoRecording::PngSequence(dir) => {
+                std::fs::create_dir_all(dir)?;
+                Ok(VideoWriter::PngSequence { dir: dir.clone(), frame: 0 })
+            },
+            VideoRecording::RawRgba(path) => Ok(VideoWriter::RawRgba(BufWriter::new(File::create(path)?)))
+        }
+    }
+
+    fn write_frame(&mut self, screen: &[u32]) -> io::Result<()> {
+
+        // The screen is ARGB8888
+        let rgba: Vec<u8> = screen
+            .iter()
+            .flat_map(|pixel| {
+                let [b, g, r, a] = pixel.to_le_bytes();
+                [r, g, b, a]
+            })
+            .collect();
+
+        match self {
+            VideoWriter::PngSequence { dir, frame } => {
+                let file = File::create(dir.join(format!("frame_{:06}.png", frame)))?;
+                let mut encoder = png::Encoder::new(BufWriter::new(file), SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(&rgba)?;
+                *frame += 1;
+                Ok(())
+            },
+            VideoWriter::RawRgba(file) => file.write_all(&rgba)
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            VideoWriter::PngSequence { .. } => Ok(()),
+            VideoWriter::RawRgba(file) => file.flush()
+        }
+    }
+
+}
+
+pub struct Recorder {
+    audio: Option<WavWriter>,
+    video: Option<VideoWriter>
+}
+
+impl Recorder {
+
+    pub fn new(options: &RecordingOptions) -> io::Result<Recorder> {
+        Ok(Recorder {
+            audio: options.audio.as_ref().map(WavWriter::new).transpose()?,
+            video: options.video.as_ref().map(VideoWriter::new).transpose()?
+        })
+    }
+
+    pub fn record_frame(&mut self, screen: &[u32], samples: &[f32]) -> io::Result<()> {
+        if let Some(audio) = self.audio.as_mut() {
+            audio.write_samples(samples)?;
+        }
+        if let Some(video) = self.video.as_mut() {
+            video.write_frame(screen)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(audio) = self.audio.as_mut() {
+            audio.finish()?;
+        }
+        if let Some(video) = self.video.as_mut() {
+            video.finish()?;
+        }
+        Ok(())
+    }
+
+}
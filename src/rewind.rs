@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+
+// Ring buffer of the last frames' save states. Only the newest state is kept
+// whole, every older one is stored as the XOR against the state that followed
+// it. Most of the machine doesn't change between frames, so the XOR is mostly
+// zeros and those runs are skipped:
+//   (zero run: u16, literal length: u16, literal bytes) ...
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Vec<u8>,
+    deltas: VecDeque<Vec<u8>>
+}
+
+impl RewindBuffer {
+
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity,
+            latest: Vec::new(),
+            deltas: VecDeque::with_capacity(capacity)
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+
+        // The state layout only changes between ROMs, but don't mix them up if it does
+        if self.latest.len() != state.len() {
+            self.deltas.clear();
+        } else {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(encode_delta(&state, &self.latest));
+        }
+
+        self.latest = state;
+    }
+
+    // Returns the state of the frame before the newest one and makes it the newest
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        apply_delta(&mut self.latest, &delta);
+        Some(&self.latest)
+    }
+
+}
+
+fn encode_delta(state: &[u8], previous: &[u8]) -> Vec<u8> {
+
+    let mut delta = Vec::new();
+    let mut pos = 0;
+
+    while pos < state.len() {
+
+        let mut zeros = 0;
+        while pos + zeros < state.len() && zeros < u16::MAX as usize && state[pos + zeros] == previous[pos + zeros] {
+            zeros += 1;
+        }
+        pos += zeros;
+
+        let start = pos;
+        while pos < state.len() && pos - start < u16::MAX as usize && state[pos] != previous[pos] {
+            pos += 1;
+        }
+
+        delta.extend_from_slice(&(zeros as u16).to_le_bytes());
+        delta.extend_from_slice(&((pos - start) as u16).to_le_bytes());
+        delta.extend(state[start .. pos].iter().zip(&previous[start .. pos]).map(|(a, b)| a ^ b));
+    }
+
+    delta
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+
+    let mut pos = 0;
+    let mut chunks = delta;
+
+    while chunks.len() >= 4 {
+        let zeros = u16::from_le_bytes([chunks[0], chunks[1]]) as usize;
+        let len = u16::from_le_bytes([chunks[2], chunks[3]]) as usize;
+        pos += zeros;
+        for (byte, xor) in state[pos .. pos + len].iter_mut().zip(&chunks[4 .. 4 + len]) {
+            *byte ^= xor;
+        }
+        pos += len;
+        chunks = &chunks[4 + len ..];
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+
+
+// Save states start with the magic, the format version and the CRC32 of the
+// ROM they were made with, followed by the state of every component in order
+const MAGIC: &[u8; 8] = b"BENISTAT";
+pub const SAVE_STATE_VERSION: u16 = 4;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    InvalidMagicError,
+    VersionMismatchError { expected: u16, found: u16 },
+    RomMismatchError { expected: u32, found: u32 },
+    TruncatedError,
+    InvalidDataError
+}
+
+impl fmt::Display for SaveStateError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::InvalidMagicError => write!(f, "not a save state file"),
+            SaveStateError::VersionMismatchError { expected, found } =>
+                write!(f, "save state format version {} isn't supported (expected version {})", found, expected),
+            SaveStateError::RomMismatchError { expected, found } =>
+                write!(f, "save state belongs to a different ROM (ROM CRC32 {:08X}, state made with {:08X})", expected, found),
+            SaveStateError::TruncatedError => write!(f, "save state is truncated"),
+            SaveStateError::InvalidDataError => write!(f, "save state contains invalid data")
+        }
+    }
+
+}
+
+impl std::error::Error for SaveStateError {}
+
+// Values are stored little endian, with no padding between them
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+
+    pub fn new(rom_crc: u32) -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.write_bytes(MAGIC);
+        writer.write_u16(SAVE_STATE_VERSION);
+        writer.write_u32(rom_crc);
+        writer
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> StateReader<'a> {
+
+    // Checks the header, the rest of the state is read by the components
+    pub fn new(data: &'a [u8], rom_crc: u32) -> Result<StateReader<'a>, SaveStateError> {
+
+        let mut reader = StateReader { data, pos: 0 };
+
+        if reader.read_bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(SaveStateError::InvalidMagicError);
+        }
+
+        let version = reader.read_u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::VersionMismatchError { expected: SAVE_STATE_VERSION, found: version });
+        }
+
+        let state_crc = reader.read_u32()?;
+        if state_crc != rom_crc {
+            return Err(SaveStateError::RomMismatchError { expected: rom_crc, found: state_crc });
+        }
+
+        Ok(reader)
+    }
+
+    // Trailing data means the state doesn't match the components layout
+    pub fn finish(self) -> Result<(), SaveStateError> {
+        if self.pos != self.data.len() {
+            return Err(SaveStateError::InvalidDataError);
+        }
+        Ok(())
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() - self.pos < len {
+            return Err(SaveStateError::TruncatedError);
+        }
+        let bytes = &self.data[self.pos .. self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), SaveStateError> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        Ok(())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidDataError)
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().expect("Array size mismatch!")))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().expect("Array size mismatch!")))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().expect("Array size mismatch!")))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, SaveStateError> {
+        Ok(f32::from_bits(self.read_u32()?))
+    }
+
+}
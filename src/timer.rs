@@ -1,4 +1,5 @@
 use crate::cpu::InterruptMask;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
 
 pub struct Timer {
@@ -6,7 +7,13 @@ pub struct Timer {
     pub tima: u8,
     pub tma: u8,
     pub tac: u8,
-    last_and: u8
+    last_and: u8,
+
+    // TIMA reads 0x00 for one M-cycle after overflowing, then it is reloaded
+    // from TMA and the interrupt is requested
+    overflow_pending: bool,
+    // Set during the M-cycle in which TIMA was reloaded
+    reloading: bool
 }
 
 impl Timer {
@@ -17,43 +24,108 @@ impl Timer {
             tima: 0x00,
             tma: 0x00,
             tac: 0xF8,
-            last_and: 0
+            last_and: 0,
+            overflow_pending: false,
+            reloading: false
+        }
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.div);
+        state.write_bytes(&[self.tima, self.tma, self.tac, self.last_and]);
+        state.write_bool(self.overflow_pending);
+        state.write_bool(self.reloading);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.div = state.read_u16()?;
+        self.tima = state.read_u8()?;
+        self.tma = state.read_u8()?;
+        self.tac = 0xF8 | (state.read_u8()? & 0x07);
+        self.last_and = state.read_u8()? & 0x01;
+        self.overflow_pending = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        Ok(())
+    }
+
+    // Writing any value to DIV clears the whole internal counter. If the
+    // selected bit was set this is a falling edge, so TIMA increments
+    pub fn reset_div(&mut self) {
+        self.div = 0;
+        self.update_edge();
+    }
+
+    // Writing TIMA in the cycle it overflowed cancels the reload and the
+    // interrupt, writing it in the cycle it is reloaded has no effect
+    pub fn write_tima(&mut self, data: u8) {
+        if self.reloading {
+            return;
+        }
+        self.tima = data;
+        self.overflow_pending = false;
+    }
+
+    // TMA is copied to TIMA again if written in the cycle TIMA is reloaded
+    pub fn write_tma(&mut self, data: u8) {
+        self.tma = data;
+        if self.reloading {
+            self.tima = data;
         }
     }
 
+    // Disabling the timer or switching to a bit that is clear can also
+    // produce a falling edge and increment TIMA
+    pub fn write_tac(&mut self, data: u8) {
+        self.tac = 0xF8 | (data & 0x07);
+        self.update_edge();
+    }
+
     pub fn tick(&mut self, m_cycles: u64, interrupt_flag: &mut u8) {
 
         for _ in 0..m_cycles {
 
+            self.reloading = false;
+            if self.overflow_pending {
+                self.overflow_pending = false;
+                self.reloading = true;
+                self.tima = self.tma;
+                *interrupt_flag |= InterruptMask::Timer as u8;
+            }
+
             self.div = self.div.wrapping_add(4);
+            self.update_edge();
+        }
+
+    }
 
-            let bit = match self.tac & 0x3 {
-                0 => 9,
-                1 => 3,
-                2 => 5,
-                3 => 7,
-                _ => unreachable!()
-            };
+    // TIMA is incremented on the falling edge of the selected DIV bit ANDed
+    // with the timer enable bit
+    fn update_edge(&mut self) {
 
-            let div_bit = ((self.div >> bit) & 1) as u8;
-            let timer_enable = (self.tac & 0x04) >> 2;
-            let and_res = div_bit & timer_enable;
+        let bit = match self.tac & 0x3 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            3 => 7,
+            _ => unreachable!()
+        };
 
-            // TIMA increment
-            if and_res < self.last_and {
+        let div_bit = ((self.div >> bit) & 1) as u8;
+        let timer_enable = (self.tac & 0x04) >> 2;
+        let and_res = div_bit & timer_enable;
 
-                self.tima = self.tima.wrapping_add(1);
+        // TIMA increment
+        if and_res < self.last_and {
 
-                // Interrupt if it overflows
-                if self.tima == 0 {
-                    self.tima = self.tma;
-                    *interrupt_flag |= InterruptMask::Timer as u8;
-                }
+            self.tima = self.tima.wrapping_add(1);
 
+            // The reload from TMA happens one M-cycle later
+            if self.tima == 0 {
+                self.overflow_pending = true;
             }
-            self.last_and = and_res;
-        }
 
+        }
+        self.last_and = and_res;
     }
 
 }